@@ -26,16 +26,26 @@ use gtk::{gio, glib};
 use super::config::VERSION;
 use super::Window;
 
-use super::i18n::i18n;
+use super::i18n::{i18n, i18n_k};
 
 use super::preferences_window::PreferencesWindow;
+use super::dbus::TunerService;
+use super::tuning::Note;
+use super::pitch_log::LogFormat;
+
+use std::{cell::RefCell, env, path::{Path, PathBuf}};
+use log::error;
 
 
 mod imp {
     use super::*;
 
     #[derive(Debug, Default)]
-    pub struct App {}
+    pub struct App {
+        pub tuner_service: RefCell<Option<TunerService>>,
+        //pitch log asked for with --log, started with the window
+        pub log_option: RefCell<Option<(PathBuf, LogFormat)>>,
+    }
 
     #[glib::object_subclass]
     impl ObjectSubclass for App {
@@ -50,10 +60,79 @@ mod imp {
             let obj = self.instance();
             obj.setup_gactions();
             obj.set_accels_for_action("app.quit", &["<primary>q"]);
+            obj.set_accels_for_action("app.open-file", &["<primary>o"]);
+            obj.add_main_options();
         }
     }
 
     impl ApplicationImpl for App {
+        // Options are read in the launching process. When an instance is
+        // already running this process only hands over to it, so the log is
+        // passed on through the start-log action
+        fn handle_local_options(&self, options: &glib::VariantDict) -> i32 {
+            let path = match options.lookup::<PathBuf>("log") {
+                Ok(Some(path)) => path,
+                _ => return -1,
+            };
+
+            let format = match options.lookup::<String>("log-format") {
+                Ok(Some(name)) => match LogFormat::from_name(&name) {
+                    Some(format) => format,
+                    None => {
+                        eprintln!("{}", i18n_k("Unknown log format {format}", &[("format", &name)]));
+                        return 2;
+                    }
+                },
+                _ => LogFormat::from_path(&path),
+            };
+
+            let application = self.instance();
+            if let Err(e) = application.register(gio::Cancellable::NONE) {
+                eprintln!("{}", e);
+                return 1;
+            }
+
+            if application.is_remote() {
+                //the running instance does not write to this terminal
+                if path == Path::new("-") {
+                    eprintln!("{}", i18n("Chromatic is already running and can not log to this terminal"));
+                    return 1;
+                }
+
+                //nor does it share this working directory
+                let path = match env::current_dir() {
+                    Ok(directory) => directory.join(path),
+                    Err(_) => path,
+                };
+                application.activate_action("start-log", Some(&(path, format.name()).to_variant()));
+                return -1;
+            }
+
+            self.log_option.replace(Some((path, format)));
+            -1
+        }
+
+        // The tuner state is exported next to the application's own object
+        fn dbus_register(&self, connection: &gio::DBusConnection, object_path: &str) -> Result<(), glib::Error> {
+            self.parent_dbus_register(connection, object_path)?;
+
+            match TunerService::register(connection, object_path) {
+                Ok(service) => {
+                    self.tuner_service.replace(Some(service));
+                },
+                //the tuner works fine without it
+                Err(e) => error!("unable to register dbus interface: {}", e),
+            }
+            Ok(())
+        }
+
+        fn dbus_unregister(&self, connection: &gio::DBusConnection, object_path: &str) {
+            if let Some(mut service) = self.tuner_service.take() {
+                service.unregister();
+            }
+            self.parent_dbus_unregister(connection, object_path);
+        }
+
         // We connect to the activate callback to create a window when the application
         // has been launched. Additionally, this callback notifies us when the user
         // tries to launch a "second instance" of the application. When they try
@@ -70,6 +149,25 @@ mod imp {
 
             // Ask the window manager/compositor to present the window
             window.present();
+
+            if let Some((path, format)) = self.log_option.take() {
+                if let Ok(window) = window.downcast::<Window>() {
+                    window.start_log(&path, format);
+                }
+            }
+        }
+
+        // Files given on the command line are analysed instead of the microphone
+        fn open(&self, files: &[gio::File], _hint: &str) {
+            self.activate();
+
+            let application = self.instance();
+            let window = application.active_window().and_then(|w| w.downcast::<Window>().ok());
+            let path = files.first().and_then(|file| file.path());
+
+            if let (Some(window), Some(path)) = (window, path) {
+                window.open_file(&path);
+            }
         }
     }
 
@@ -88,6 +186,26 @@ impl App {
         glib::Object::new(&[("application-id", &application_id), ("flags", flags)])
     }
 
+    //options shared with the terminal mode, see cli.rs
+    fn add_main_options(&self) {
+        self.add_main_option(
+            "log",
+            glib::Char::from(0),
+            glib::OptionFlags::NONE,
+            glib::OptionArg::Filename,
+            &i18n("Write every analysed frame to FILE, - for stdout"),
+            Some("FILE"),
+        );
+        self.add_main_option(
+            "log-format",
+            glib::Char::from(0),
+            glib::OptionFlags::NONE,
+            glib::OptionArg::String,
+            &i18n("Format of the log (json, csv), guessed from FILE by default"),
+            Some("FORMAT"),
+        );
+    }
+
     fn setup_gactions(&self) {
         let quit_action = gio::ActionEntry::builder("quit")
             .activate(move |app: &Self, _, _| app.quit())
@@ -98,8 +216,31 @@ impl App {
         let preferences_action = gio::ActionEntry::builder("preferences")
             .activate(move |app: &Self, _, _| app.show_preferences())
             .build();
+        let open_file_action = gio::ActionEntry::builder("open-file")
+            .activate(move |app: &Self, _, _| app.open_file())
+            .build();
+        //sent by a second launch with --log, the path and format name
+        let start_log_action = gio::ActionEntry::builder("start-log")
+            .parameter_type("(ays)")
+            .activate(move |app: &Self, _, parameter| {
+                if let Some((path, name)) = parameter.and_then(|p| p.get::<(PathBuf, String)>()) {
+                    let format = LogFormat::from_name(&name).unwrap_or_else(|| LogFormat::from_path(&path));
+                    app.start_log(path, format);
+                }
+            })
+            .build();
 
-        self.add_action_entries([quit_action, about_action, preferences_action]).unwrap();
+        self.add_action_entries([quit_action, about_action, preferences_action, open_file_action, start_log_action]).unwrap();
+    }
+
+    //start the log in the window, or once it is opened
+    fn start_log(&self, path: PathBuf, format: LogFormat) {
+        match self.active_window().and_then(|w| w.downcast::<Window>().ok()) {
+            Some(window) => window.start_log(&path, format),
+            None => {
+                self.imp().log_option.replace(Some((path, format)));
+            }
+        }
     }
 
     fn show_about(&self) {
@@ -131,6 +272,19 @@ impl App {
         about.present();
     }
 
+    //share the pitch shown in the window over dbus
+    pub fn publish_pitch(&self, note: Option<&Note>, frequency: f32) {
+        if let Some(service) = self.imp().tuner_service.borrow().as_ref() {
+            service.update(note, frequency);
+        }
+    }
+
+    fn open_file(&self) {
+        if let Some(window) = self.active_window().and_then(|w| w.downcast::<Window>().ok()) {
+            window.choose_audio_file();
+        }
+    }
+
     fn show_preferences(&self) {
         let preferences = PreferencesWindow::new();
         let window = self.active_window().unwrap();
@@ -0,0 +1,74 @@
+/* audio_source.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//the interface between the recorder and the audio backends. a source lists its
+//devices and runs a stream on one of them, handing every buffer to an Analyser
+//which sends the results to the window
+
+use gtk::glib::Sender;
+
+use std::{error::Error, fmt};
+
+use super::recorder::AnalysisSettings;
+use super::window::AudioAction;
+
+#[derive(Debug)]
+pub struct AudioSourceError(pub String);
+
+impl fmt::Display for AudioSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for AudioSourceError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputDevice {
+    //what the backend finds the device by, stable where the backend allows it
+    pub id: String,
+    //shown to the user
+    pub name: String,
+}
+
+impl InputDevice {
+    //for backends where the shown name is all there is to go by
+    pub fn named(name: &str) -> InputDevice {
+        InputDevice {
+            id: name.to_string(),
+            name: name.to_string(),
+        }
+    }
+}
+
+pub trait AudioSource: fmt::Debug {
+    fn devices(&self) -> Result<Vec<InputDevice>, Box<dyn Error>>;
+
+    //the device the rest of the system is recording from, if the backend can tell
+    fn running_device(&self) -> Result<InputDevice, Box<dyn Error>> {
+        Err(Box::new(AudioSourceError("running device unknown".into())))
+    }
+
+    //start a stream on device, or the backend's default for None, replacing any
+    //running stream. buffers of analysis.buffer_size samples go to the analyser
+    fn start(&mut self, device: Option<&InputDevice>, analysis: AnalysisSettings, sender: Sender<AudioAction>) -> Result<(), Box<dyn Error>>;
+
+    fn stop(&mut self);
+}
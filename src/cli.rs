@@ -0,0 +1,314 @@
+/* cli.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//terminal mode, `chromatic --cli`: runs the recorder without any window and
+//prints the detected note to stdout
+
+use gtk::{gio, glib};
+use gtk::prelude::*;
+
+use std::{error::Error, fmt, io::Write, path::Path};
+use std::time::{Duration, Instant};
+use log::error;
+
+use super::i18n::{i18n, i18n_k};
+use super::recorder::Recorder;
+use super::window::AudioAction;
+use super::tuning::{Note, TuningSystem};
+use super::pitch_log::{LogFormat, PitchLog};
+use super::instruments::{self, InstrumentPreset};
+use super::util;
+
+//characters of the ascii needle, odd so there is a centre
+const NEEDLE_WIDTH: i32 = 41;
+
+#[derive(Debug)]
+struct CliError(String);
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for CliError {}
+
+pub fn wants_cli(args: &[String]) -> bool {
+    args.iter().skip(1).any(|arg| arg == "--cli")
+}
+
+fn usage() -> String {
+    i18n("Usage: chromatic --cli [OPTION…]
+
+Options:
+  --reference-pitch HZ    Frequency of A4
+  --backend NAME          Audio backend (portaudio, pipewire, jack, synthetic)
+  --device NAME           Input device, or test signal of the synthetic backend
+  --algorithm NAME        Pitch detection algorithm (yin, yinfft, yinfast, mcomb, fcomb, schmitt, specacf)
+  --set KEY=VALUE         Override any setting for this run
+  --log FILE              Write every analysed frame to FILE, - for stdout
+  --log-format FORMAT     Format of the log (json, csv), guessed from FILE by default
+  -h, --help              Show this help
+
+Settings not given on the command line are read from GSettings. Overrides are not saved.")
+}
+
+//set a key from its text form, strings may be given without quotes
+fn set_key(settings: &gio::Settings, key: &str, text: &str) -> Result<(), Box<dyn Error>> {
+    let schema = settings
+        .settings_schema()
+        .ok_or_else(|| CliError(i18n("Settings schema not found")))?;
+
+    if !schema.has_key(key) {
+        return Err(Box::new(CliError(i18n_k("Unknown setting {key}", &[("key", key)]))));
+    }
+
+    let value_type = schema.key(key).value_type();
+    let value = match glib::Variant::parse(Some(&*value_type), text) {
+        Ok(value) => value,
+        Err(_) if value_type.as_str() == "s" => text.to_variant(),
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    settings.set_value(key, &value)?;
+    Ok(())
+}
+
+//options of the terminal mode that are not settings
+#[derive(Debug, Default)]
+struct Options {
+    log: Option<String>,
+    log_format: Option<LogFormat>,
+}
+
+//apply the command line to settings, Ok(None) when only help was asked for
+fn apply_arguments(settings: &gio::Settings, args: &[String]) -> Result<Option<Options>, Box<dyn Error>> {
+    let mut options = Options::default();
+    let mut args = args.iter().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| CliError(i18n_k("Missing value for {option}", &[("option", arg)])))
+        };
+
+        match arg.as_str() {
+            "--cli" => (),
+            "-h" | "--help" => return Ok(None),
+            "--reference-pitch" => set_key(settings, "reference-pitch", &value()?)?,
+            "--algorithm" => set_key(settings, "pitch-algorithm", &value()?)?,
+            "--backend" => set_key(settings, "audio-backend", &value()?)?,
+            "--device" => {
+                set_key(settings, "selected-device", &value()?)?;
+                //the id stored for the last device chosen would win over the new name
+                settings.set_string("selected-device-id", "")?;
+                settings.set_boolean("choose-device", true)?;
+            }
+            "--set" => {
+                let assignment = value()?;
+                let (key, text) = assignment
+                    .split_once('=')
+                    .ok_or_else(|| CliError(i18n_k("Expected KEY=VALUE, got {value}", &[("value", &assignment)])))?;
+                set_key(settings, key, text)?;
+            }
+            "--log" => options.log = Some(value()?),
+            "--log-format" => {
+                let name = value()?;
+                options.log_format = Some(
+                    LogFormat::from_name(&name)
+                        .ok_or_else(|| CliError(i18n_k("Unknown log format {format}", &[("format", &name)])))?,
+                );
+            }
+            _ => return Err(Box::new(CliError(i18n_k("Unknown option {option}", &[("option", arg)])))),
+        }
+    }
+
+    Ok(Some(options))
+}
+
+//[----------#---------|--------------------]
+fn needle(cents: f64, range: f64) -> String {
+    let half = NEEDLE_WIDTH / 2;
+    let position = half + ((cents / (range / 2.0)).clamp(-1.0, 1.0) * half as f64).round() as i32;
+
+    let scale: String = (0..NEEDLE_WIDTH)
+        .map(|i| match i {
+            i if i == position => '#',
+            i if i == half => '|',
+            _ => '-',
+        })
+        .collect();
+
+    format!("[{}]", scale)
+}
+
+struct Tuner {
+    settings: gio::Settings,
+    tuning: TuningSystem,
+    instrument: Option<InstrumentPreset>,
+    last_heard: Option<Instant>,
+    cleared: bool,
+    log: Option<PitchLog>,
+    //the log owns stdout, so the tuner display is left out
+    quiet: bool,
+}
+
+impl Tuner {
+    fn new(settings: gio::Settings) -> Result<Tuner, Box<dyn Error>> {
+        Ok(Tuner {
+            tuning: TuningSystem::from_settings(&settings)?,
+            instrument: instruments::selected_preset(&settings),
+            settings,
+            last_heard: None,
+            cleared: false,
+            log: None,
+            quiet: false,
+        })
+    }
+
+    fn log_frame(&mut self, frequency: f32, confidence: f32, note: Option<&Note>) {
+        if let Some(log) = self.log.as_mut() {
+            if let Err(e) = log.write_frame(frequency, confidence, note) {
+                eprintln!("{}", i18n_k("Pitch log stopped ({error})", &[("error", &e.to_string())]));
+                self.log = None;
+            }
+        }
+    }
+
+    fn print(&self, line: &str) {
+        if self.quiet {
+            return;
+        }
+
+        //rewrite the current line in place
+        print!("\r{}\x1b[K", line);
+        if let Err(e) = std::io::stdout().flush() {
+            error!("{}", e);
+        }
+    }
+
+    fn update_frequency(&mut self, frequency: f32, confidence: f32) {
+        let minimum_confidence = self.settings.double("minimum-confidence") as f32;
+
+        if frequency <= 0.0 || confidence < minimum_confidence {
+            self.log_frame(frequency, confidence, None);
+
+            let hang = Duration::from_secs_f64(self.settings.double("label-hang"));
+            let expired = self.last_heard.map(|t| t.elapsed() > hang).unwrap_or(true);
+            if expired && !self.cleared {
+                self.print("--");
+                self.cleared = true;
+            }
+            return;
+        }
+
+        self.last_heard = Some(Instant::now());
+        self.cleared = false;
+
+        let reference = self.settings.double("reference-pitch");
+        let transposition = self.settings.int("transposition");
+
+        let string = self
+            .instrument
+            .as_ref()
+            .and_then(|preset| preset.nearest_string(frequency as f64, reference, transposition));
+
+        let (note, range) = match string {
+            Some((_, note)) => (note, 100.0),
+            None => (self.tuning.nearest_note(frequency as f64, reference, transposition), self.tuning.step_size()),
+        };
+
+        self.log_frame(frequency, confidence, Some(&note));
+
+        let cents = match self.settings.boolean("show-cent-decimals") {
+            true => format!("{:+.1}", note.cents),
+            false => format!("{:+.0}", note.cents),
+        };
+
+        self.print(&format!(
+            "{:<6} {:>9.2} Hz {:>7} cents  {}",
+            format!("{}{}", note.name, note.octave),
+            frequency,
+            cents,
+            needle(note.cents, range),
+        ));
+    }
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let settings = util::override_settings();
+
+    let options = match apply_arguments(&settings, args) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", usage());
+            return 0;
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, usage());
+            return 2;
+        }
+    };
+
+    let mut tuner = match Tuner::new(settings) {
+        Ok(tuner) => tuner,
+        Err(e) => {
+            eprintln!("{}", i18n_k("Unable to load Scala file ({error})", &[("error", &e.to_string())]));
+            return 1;
+        }
+    };
+
+    if let Some(target) = options.log.as_deref() {
+        let path = Path::new(target);
+        let format = options.log_format.unwrap_or_else(|| LogFormat::from_path(path));
+        match PitchLog::create(path, format) {
+            Ok(log) => tuner.log = Some(log),
+            Err(e) => {
+                eprintln!("{}", i18n_k("Unable to create pitch log ({error})", &[("error", &e.to_string())]));
+                return 1;
+            }
+        }
+        tuner.quiet = target == "-";
+    }
+
+    let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+    let recorder = Recorder::new(sender);
+
+    if let Err(e) = recorder.setup() {
+        eprintln!("{} {}", i18n("Unable to initialize audio backend."), e);
+        return 1;
+    }
+
+    receiver.attach(None, move |action| {
+        match action {
+            AudioAction::Pitch(frequency, confidence, _) => tuner.update_frequency(frequency, confidence),
+            //the tuner line is overwritten in place, so start the message on its own
+            AudioAction::Error(message) => eprintln!("\n{}", message),
+            _ => (),
+        }
+        glib::Continue(true)
+    });
+
+    let main_loop = glib::MainLoop::new(None, false);
+    main_loop.run();
+
+    0
+}
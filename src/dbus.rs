@@ -0,0 +1,269 @@
+/* dbus.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//session bus interface to the live tuner state, registered next to the
+//application's own object, e.g.
+//  gdbus call --session --dest io.github.nate_xyz.Chromatic \
+//    --object-path /io/github/nate_xyz/Chromatic \
+//    --method io.github.nate_xyz.Chromatic.Tuner.SetReferencePitch 442
+
+use gtk::{gio, glib};
+use gtk::prelude::*;
+
+use std::{cell::{Cell, RefCell}, collections::HashMap, rc::Rc};
+use std::time::{Duration, Instant};
+use log::{debug, error};
+
+use super::tuning::Note;
+use super::util;
+use super::window::Window;
+
+pub const INTERFACE_NAME: &str = "io.github.nate_xyz.Chromatic.Tuner";
+
+//the frequency and cents of a held note change on nearly every frame, their
+//changes are signalled at most this often so the bus is not flooded
+const PROPERTIES_INTERVAL: Duration = Duration::from_millis(100);
+
+const INTERFACE_XML: &str = r#"
+<node>
+  <interface name="io.github.nate_xyz.Chromatic.Tuner">
+    <property name="Note" type="s" access="read"/>
+    <property name="Frequency" type="d" access="read"/>
+    <property name="Cents" type="d" access="read"/>
+    <property name="InputDevice" type="s" access="read"/>
+    <signal name="PitchChanged">
+      <arg name="note" type="s"/>
+      <arg name="frequency" type="d"/>
+      <arg name="cents" type="d"/>
+    </signal>
+    <method name="SetInputDevice">
+      <arg name="name" type="s" direction="in"/>
+    </method>
+    <method name="SetReferencePitch">
+      <arg name="frequency" type="d" direction="in"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+//what the tuner shows, the note is empty while nothing is heard
+#[derive(Clone, Debug, Default, PartialEq)]
+struct TunerState {
+    note: String,
+    frequency: f64,
+    cents: f64,
+}
+
+#[derive(Debug)]
+pub struct TunerService {
+    connection: gio::DBusConnection,
+    object_path: String,
+    registration: Option<gio::RegistrationId>,
+    state: Rc<RefCell<TunerState>>,
+    //the state last signalled and when
+    signalled: RefCell<TunerState>,
+    signalled_at: Cell<Option<Instant>>,
+    //kept to be told when the input device changes
+    settings: gio::Settings,
+    device_handler: Option<glib::SignalHandlerId>,
+}
+
+//the recorder writes every device it starts streaming from here, chosen or not
+fn input_device() -> String {
+    util::settings_manager().string("selected-device").to_string()
+}
+
+fn emit(connection: &gio::DBusConnection, object_path: &str, interface: &str, signal: &str, parameters: glib::Variant) {
+    if let Err(e) = connection.emit_signal(None, object_path, interface, signal, Some(&parameters)) {
+        error!("dbus -> unable to emit {}: {}", signal, e);
+    }
+}
+
+fn properties_changed(changed: HashMap<&str, glib::Variant>) -> glib::Variant {
+    (INTERFACE_NAME, changed, Vec::<String>::new()).to_variant()
+}
+
+//methods need the window's recorder, there is none while no window is open
+fn tuner_window(invocation: &gio::DBusMethodInvocation) -> Option<Window> {
+    let window = util::active_window().and_then(|w| w.downcast::<Window>().ok());
+    if window.is_none() {
+        invocation.clone().return_error(gio::DBusError::Failed, "no tuner window is open");
+    }
+    window
+}
+
+fn property(state: &TunerState, name: &str) -> glib::Variant {
+    match name {
+        "Note" => state.note.to_variant(),
+        "Frequency" => state.frequency.to_variant(),
+        "Cents" => state.cents.to_variant(),
+        "InputDevice" => input_device().to_variant(),
+        _ => {
+            error!("dbus -> unknown property {}", name);
+            "".to_variant()
+        }
+    }
+}
+
+fn call_method(method_name: &str, parameters: &glib::Variant, invocation: gio::DBusMethodInvocation) {
+    match method_name {
+        "SetInputDevice" => {
+            let (name,) = parameters.get::<(String,)>().unwrap_or_default();
+            debug!("dbus -> set input device {}", name);
+
+            //the window restarts the stream when the settings change
+            if tuner_window(&invocation).is_none() {
+                return;
+            }
+
+            //both keys change at once, so the stream restarts only for the pair
+            let settings = util::settings_manager();
+            settings.delay();
+            let result = settings
+                .set_boolean("choose-device", !name.is_empty())
+                .and_then(|_| settings.set_string("selected-device", &name));
+            if let Err(e) = result {
+                settings.revert();
+                invocation.return_dbus_error("org.freedesktop.DBus.Error.InvalidArgs", &e.to_string());
+                return;
+            }
+            settings.apply();
+            invocation.return_value(None);
+        }
+        "SetReferencePitch" => {
+            let (frequency,) = parameters.get::<(f64,)>().unwrap_or_default();
+            debug!("dbus -> set reference pitch {}", frequency);
+
+            let window = match tuner_window(&invocation) {
+                Some(window) => window,
+                None => return,
+            };
+
+            match util::settings_manager().set_double("reference-pitch", frequency) {
+                Ok(_) => {
+                    window.update_settings();
+                    invocation.return_value(None);
+                }
+                //out of the range of the key
+                Err(e) => invocation.return_dbus_error("org.freedesktop.DBus.Error.InvalidArgs", &e.to_string()),
+            }
+        }
+        _ => invocation.return_dbus_error("org.freedesktop.DBus.Error.UnknownMethod", method_name),
+    }
+}
+
+impl TunerService {
+    pub fn register(connection: &gio::DBusConnection, object_path: &str) -> Result<TunerService, glib::Error> {
+        let node = gio::DBusNodeInfo::for_xml(INTERFACE_XML)?;
+        let interface = node.lookup_interface(INTERFACE_NAME).unwrap();
+        let state = Rc::new(RefCell::new(TunerState::default()));
+
+        let get_state = state.clone();
+        let registration = connection.register_object(
+            object_path,
+            &interface,
+            move |_, _, _, _, method_name, parameters, invocation| {
+                call_method(method_name, &parameters, invocation);
+            },
+            move |_, _, _, _, property_name| property(&get_state.borrow(), property_name),
+            //every property is read only
+            move |_, _, _, _, _, _| false,
+        )?;
+
+        let settings = util::settings_manager();
+        let device_handler = settings.connect_changed(Some("selected-device"), {
+            let connection = connection.clone();
+            let object_path = object_path.to_string();
+            move |_, _| {
+                let mut changed = HashMap::new();
+                changed.insert("InputDevice", input_device().to_variant());
+                emit(&connection, &object_path, "org.freedesktop.DBus.Properties", "PropertiesChanged", properties_changed(changed));
+            }
+        });
+
+        debug!("dbus -> registered {} at {}", INTERFACE_NAME, object_path);
+
+        Ok(TunerService {
+            connection: connection.clone(),
+            object_path: object_path.to_string(),
+            registration: Some(registration),
+            state,
+            signalled: RefCell::new(TunerState::default()),
+            signalled_at: Cell::new(None),
+            settings,
+            device_handler: Some(device_handler),
+        })
+    }
+
+    //called for every analysed frame. the properties are signalled right away
+    //when another note is heard, along with PitchChanged, otherwise at most
+    //every PROPERTIES_INTERVAL
+    pub fn update(&self, note: Option<&Note>, frequency: f32) {
+        let new_state = match note {
+            Some(note) => TunerState {
+                note: format!("{}{}", note.name, note.octave),
+                frequency: (frequency as f64 * 100.0).round() / 100.0,
+                cents: (note.cents * 10.0).round() / 10.0,
+            },
+            None => TunerState::default(),
+        };
+
+        //reads always get the latest state
+        self.state.replace(new_state.clone());
+
+        if *self.signalled.borrow() == new_state {
+            return;
+        }
+
+        let note_changed = self.signalled.borrow().note != new_state.note;
+        let due = match self.signalled_at.get() {
+            Some(at) => at.elapsed() >= PROPERTIES_INTERVAL,
+            None => true,
+        };
+        if !note_changed && !due {
+            return;
+        }
+
+        let mut changed = HashMap::new();
+        changed.insert("Note", new_state.note.to_variant());
+        changed.insert("Frequency", new_state.frequency.to_variant());
+        changed.insert("Cents", new_state.cents.to_variant());
+
+        let pitch_changed = (new_state.note.clone(), new_state.frequency, new_state.cents).to_variant();
+        self.signalled.replace(new_state);
+        self.signalled_at.set(Some(Instant::now()));
+
+        if note_changed {
+            emit(&self.connection, &self.object_path, INTERFACE_NAME, "PitchChanged", pitch_changed);
+        }
+        emit(&self.connection, &self.object_path, "org.freedesktop.DBus.Properties", "PropertiesChanged", properties_changed(changed));
+    }
+
+    pub fn unregister(&mut self) {
+        if let Some(handler) = self.device_handler.take() {
+            self.settings.disconnect(handler);
+        }
+        if let Some(registration) = self.registration.take() {
+            if let Err(e) = self.connection.unregister_object(registration) {
+                error!("dbus -> unable to unregister {}: {}", INTERFACE_NAME, e);
+            }
+        }
+    }
+}
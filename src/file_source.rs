@@ -0,0 +1,335 @@
+/* file_source.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//analysis of audio files instead of the microphone. the file is decoded with
+//symphonia on a thread and its samples are fed through the recorder's analyser
+
+use gtk::glib::Sender;
+
+use std::{error::Error, fmt, fs::File, path::{Path, PathBuf}, thread};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use log::{debug, error};
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::{Time, TimeBase};
+
+use super::i18n::i18n_k;
+use super::recorder::{Analyser, AnalysisSettings};
+use super::window::AudioAction;
+
+//how often the playback position is reported, in seconds
+const POSITION_INTERVAL: f64 = 0.1;
+
+#[derive(Debug)]
+struct FileSourceError(String);
+
+impl fmt::Display for FileSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for FileSourceError {}
+
+#[derive(Clone, Copy, Debug)]
+enum Command {
+    Play,
+    Pause,
+    Seek(f64),
+    Realtime(bool),
+}
+
+struct Decoding {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: Option<TimeBase>,
+    sample_rate: u32,
+    duration: Option<f64>,
+}
+
+fn open(path: &Path) -> Result<Decoding, Box<dyn Error>> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe().format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())?;
+    let format = probed.format;
+
+    let track = format
+        .default_track()
+        .ok_or_else(|| FileSourceError("no audio track".into()))?;
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or_else(|| FileSourceError("unknown sample rate".into()))?;
+
+    let time_base = track.codec_params.time_base;
+    let duration = match (time_base, track.codec_params.n_frames) {
+        (Some(time_base), Some(frames)) => Some(seconds(time_base.calc_time(frames))),
+        _ => None,
+    };
+
+    let decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    Ok(Decoding {
+        track_id: track.id,
+        format,
+        decoder,
+        time_base,
+        sample_rate,
+        duration,
+    })
+}
+
+fn seconds(time: Time) -> f64 {
+    time.seconds as f64 + time.frac
+}
+
+//move to target seconds, returning the position actually reached
+fn seek(decoding: &mut Decoding, target: f64) -> Result<f64, DecodeError> {
+    let seeked = decoding.format.seek(
+        SeekMode::Accurate,
+        SeekTo::Time { time: Time::from(target.max(0.0)), track_id: Some(decoding.track_id) },
+    )?;
+    decoding.decoder.reset();
+
+    Ok(match decoding.time_base {
+        Some(time_base) => seconds(time_base.calc_time(seeked.actual_ts)),
+        None => target,
+    })
+}
+
+//a file being analysed. dropping it ends the decoding thread
+#[derive(Debug)]
+pub struct FileSource {
+    path: PathBuf,
+    duration: Option<f64>,
+    control: mpsc::Sender<Command>,
+}
+
+impl FileSource {
+    //starts paused, the position is reported with AudioAction::FilePosition
+    pub fn open(path: &Path, analysis: AnalysisSettings, realtime: bool, sender: Sender<AudioAction>) -> Result<FileSource, Box<dyn Error>> {
+        //probe here so unreadable files are reported to the caller
+        let decoding = open(path)?;
+        let duration = decoding.duration;
+        let (control, commands) = mpsc::channel::<Command>();
+
+        debug!("file source -> {} {} Hz {:?} s", path.display(), decoding.sample_rate, duration);
+
+        thread::spawn(move || {
+            if let Err(e) = run(decoding, analysis, realtime, commands, sender) {
+                error!("file source error: {}", e);
+            }
+            debug!("file source closing ...");
+        });
+
+        Ok(FileSource {
+            path: path.to_path_buf(),
+            duration,
+            control,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    //length in seconds, if the container knows it
+    pub fn duration(&self) -> Option<f64> {
+        self.duration
+    }
+
+    pub fn play(&self) {
+        self.send(Command::Play);
+    }
+
+    pub fn pause(&self) {
+        self.send(Command::Pause);
+    }
+
+    pub fn seek(&self, position: f64) {
+        self.send(Command::Seek(position));
+    }
+
+    //follow the file in real time, or analyse it as fast as possible
+    pub fn set_realtime(&self, realtime: bool) {
+        self.send(Command::Realtime(realtime));
+    }
+
+    fn send(&self, command: Command) {
+        if let Err(e) = self.control.send(command) {
+            error!("file source control error: {}", e);
+        }
+    }
+}
+
+fn run(
+    mut decoding: Decoding,
+    analysis: AnalysisSettings,
+    mut realtime: bool,
+    commands: mpsc::Receiver<Command>,
+    sender: Sender<AudioAction>,
+) -> Result<(), Box<dyn Error>> {
+    let sample_rate = decoding.sample_rate as f64;
+    let buffer_size = analysis.buffer_size as usize;
+    //e.g. a detection method that cannot use the buffer size
+    let mut analyser = match Analyser::new(analysis, sample_rate, sender.clone()) {
+        Ok(analyser) => analyser,
+        Err(e) => {
+            error!("file source -> unable to create analyser: {}", e);
+            sender.send(AudioAction::Error(i18n_k("Unable to analyse audio ({error})", &[("error", &e.to_string())])))?;
+            return Ok(());
+        }
+    };
+
+    let mut playing = false;
+    let mut finished = false;
+    let mut pending: Vec<f32> = Vec::with_capacity(2 * buffer_size);
+    let mut position = 0.0; //seconds of the file analysed so far
+    let mut reported = -POSITION_INTERVAL;
+    //real time pacing, the instant and file position playback (re)started from
+    let mut clock = (Instant::now(), 0.0);
+
+    loop {
+        //wait for a command while paused or at the end, otherwise only check
+        let command = match playing && !finished {
+            true => match commands.try_recv() {
+                Ok(command) => Some(command),
+                Err(mpsc::TryRecvError::Empty) => None,
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            },
+            false => match commands.recv() {
+                Ok(command) => Some(command),
+                Err(_) => break,
+            },
+        };
+
+        //playing again after the end starts over
+        let command = match command {
+            Some(Command::Play) if finished => {
+                playing = true;
+                Some(Command::Seek(0.0))
+            }
+            command => command,
+        };
+
+        match command {
+            Some(Command::Play) => {
+                playing = true;
+                clock = (Instant::now(), position);
+            }
+            Some(Command::Pause) => playing = false,
+            Some(Command::Realtime(value)) => {
+                realtime = value;
+                clock = (Instant::now(), position);
+            }
+            Some(Command::Seek(target)) => {
+                //a failed seek leaves the file where it was, it can still be played
+                match seek(&mut decoding, target) {
+                    Ok(reached) => {
+                        pending.clear();
+                        finished = false;
+                        position = reached;
+                    }
+                    Err(e) => {
+                        error!("file source -> unable to seek to {}: {}", target, e);
+                        sender.send(AudioAction::Error(i18n_k("Unable to seek ({error})", &[("error", &e.to_string())])))?;
+                    }
+                }
+                clock = (Instant::now(), position);
+                sender.send(AudioAction::FilePosition(position))?;
+            }
+            None => (),
+        }
+
+        if !playing || finished {
+            continue;
+        }
+
+        let packet = match decoding.format.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                finished = true;
+                sender.send(AudioAction::FileFinished)?;
+                continue;
+            }
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        if packet.track_id() != decoding.track_id {
+            continue;
+        }
+
+        let decoded = match decoding.decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            //a corrupt packet is skipped, like players do
+            Err(DecodeError::DecodeError(e)) => {
+                debug!("file source -> skipping packet: {}", e);
+                continue;
+            }
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count().max(1);
+        let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        samples.copy_interleaved_ref(decoded);
+
+        //mix down to mono, the recorder listens to a single channel too
+        pending.extend(
+            samples
+                .samples()
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+
+        while pending.len() >= buffer_size {
+            analyser.process(&pending[..buffer_size]);
+            pending.drain(..buffer_size);
+            position += buffer_size as f64 / sample_rate;
+
+            if realtime {
+                let due = Duration::from_secs_f64((position - clock.1).max(0.0));
+                if let Some(wait) = due.checked_sub(clock.0.elapsed()) {
+                    thread::sleep(wait);
+                }
+            }
+
+            if (position - reported).abs() >= POSITION_INTERVAL {
+                reported = position;
+                sender.send(AudioAction::FilePosition(position))?;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -41,7 +41,7 @@ use super::util;
 
 #[derive(Clone, Debug)]
 pub enum GaugeAction {
-    UpdateGaugePos(i32),
+    UpdateGaugePos(f64),
 }
 
 mod imp {
@@ -62,7 +62,7 @@ mod imp {
         pub sender: RefCell<Option<Sender<GaugeAction>>>,
         pub receiver: RefCell<Option<Receiver<GaugeAction>>>,
 
-        pub tx: RefCell<Option<mpsc::Sender<i32>>>,
+        pub tx: RefCell<Option<mpsc::Sender<f64>>>,
 
         pub settings: gio::Settings,
         
@@ -172,7 +172,11 @@ impl Gauge {
     }
 
     //update position externally
-    pub fn set_gauge_position(&self, cents: i32) {
+    pub fn set_gauge_position(&self, cents: f64) {
+        //keep the pin on the scale when far from the target
+        let limit = self.imp().gauge_range.get() / 2.0;
+        let cents = cents.clamp(-limit, limit);
+
         if !self.imp().tx.borrow().as_ref().is_none() {
             match self.imp().tx.borrow().as_ref().unwrap().send(cents) {
                 Ok(_) => (),
@@ -181,9 +185,18 @@ impl Gauge {
         }
     }
 
+    //scale the gauge to span range cents, the distance between neighbouring notes
+    pub fn set_gauge_range(&self, range: f64) {
+        let imp = self.imp();
+        if imp.gauge_range.get() != range {
+            imp.gauge_range.set(range);
+            self.start_drawing_thread();
+            imp.drawing_area.queue_draw();
+        }
+    }
+
     //update from internal channel
-    fn update_gauge_position(&self, pos: i32) {
-        let pos = pos as f64;
+    fn update_gauge_position(&self, pos: f64) {
         //debug!("update_gauge_position {}", pos);
         if self.imp().gauge_pos.get() != pos {
             self.imp().gauge_pos.set(pos);
@@ -203,16 +216,16 @@ impl Gauge {
         
         self.retrieve_settings();
 
-        let mut end_goal = 0; //where the gauge is try to get to at any moment. changes from input on channel
-        let mut current_pos = imp.gauge_pos.get() as i32; //current gauge position
+        let mut end_goal = 0.0; //where the gauge is try to get to at any moment. changes from input on channel
+        let mut current_pos = imp.gauge_pos.get(); //current gauge position
         let refresh_milli = 8; //thread refresh rate
         let hover_amount = (1000.0 * imp.hover_time.get()) as u64; //hover time when no msg received on channel before return to baseline position
-        let rest_position = imp.gauge_rest_position.get(); //baseline position
+        let rest_position = imp.gauge_rest_position.get() as f64 * imp.gauge_range.get() / 100.0; //baseline position, scaled to the gauge range
         let mut hover_time: Option<std::time::Instant> = None;
         debug!("hover time {}, rest_position {}", hover_amount, rest_position);
 
         let glib_sender = imp.sender.borrow().as_ref().unwrap().clone();
-        let (tx, rx) = mpsc::channel::<i32>();
+        let (tx, rx) = mpsc::channel::<f64>();
 
         if !imp.tx.borrow().as_ref().is_none() {
             debug!("killing previous thread with drop send");
@@ -262,13 +275,14 @@ impl Gauge {
 
 
 
+            //move a cent per tick, landing exactly on fractional goals
             if current_pos != end_goal {
-                if (current_pos - end_goal).abs() < 2 {
+                if (current_pos - end_goal).abs() <= 1.0 {
                     current_pos = end_goal
                 } else if current_pos < end_goal {
-                    current_pos += 1;
+                    current_pos += 1.0;
                 } else {
-                    current_pos -= 1;
+                    current_pos -= 1.0;
                 }
             }
 
@@ -283,7 +297,7 @@ impl Gauge {
 
     fn draw(&self, context: &cairo::Context) -> Result<(), Box<dyn Error>> {
         //let bg_color = "#2c3338";
-        //let color = self.hex_to_rgb(bg_color);
+        //let color = util::hex_to_rgb(bg_color);
         //context.set_source_rgb(0.0, 0.0, 0.0);
         // /context.paint()?;
         self.redraw_bg(context)?;
@@ -311,13 +325,6 @@ impl Gauge {
         Ok(())
     }
 
-    fn hex_to_rgb(&self, hex_string: &str) -> (f64, f64, f64) {
-        let r = u8::from_str_radix(&hex_string[1..3], 16).unwrap() as f64 / 255.0;
-        let g = u8::from_str_radix(&hex_string[3..5], 16).unwrap() as f64 / 255.0;
-        let b = u8::from_str_radix(&hex_string[5..7], 16).unwrap() as f64 / 255.0;
-        (r, g, b)
-    }
-
     fn redraw_bg(&self, context: &cairo::Context) -> Result<(), Box<dyn Error>> {
         let gauge_gauge_center_y = 0.94;
         let gauge_cents_bar_stroke = 0.025;
@@ -383,7 +390,7 @@ impl Gauge {
         context.set_line_width(ok_bar_stroke);
         context.set_line_cap(cairo::LineCap::Butt);
         //context.set_source_rgba(*hex_to_rgba(gauge_ko_color));
-        let color = self.hex_to_rgb(gauge_ko_color);
+        let color = util::hex_to_rgb(gauge_ko_color);
         context.set_source_rgb(color.0, color.1, color.2);
         context.arc(
             gauge_center.0,
@@ -394,7 +401,7 @@ impl Gauge {
         );
         context.stroke()?;
         //context.set_source_rgba(*hex_to_rgba(gauge_ok_color));
-        let color = self.hex_to_rgb(gauge_ok_color);
+        let color = util::hex_to_rgb(gauge_ok_color);
         context.set_source_rgb(color.0, color.1, color.2);
         context.arc(
             gauge_center.0,
@@ -408,7 +415,7 @@ impl Gauge {
         // #draw cents bar
         context.set_line_width(cents_bar_stroke);
         //context.set_source_rgba(*hex_to_rgba(gauge_cents_bar_color));
-        let color = self.hex_to_rgb(gauge_cents_bar_color);
+        let color = util::hex_to_rgb(gauge_cents_bar_color);
         context.set_source_rgb(color.0, color.1, color.2);
 
         context.arc(
@@ -421,7 +428,7 @@ impl Gauge {
         context.stroke()?;
 
         // #cent tics
-        let gauge_range = 100.0;
+        let gauge_range = self.imp().gauge_range.get();
         let max_minor_divisions = 20.0;
         let cents_per_minor_division: f64 = gauge_range / max_minor_divisions;
         let base = f64::powf(10.0, cents_per_minor_division.log10().floor());
@@ -523,7 +530,7 @@ impl Gauge {
         // #draw frequency bar
         context.set_line_width(frequency_bar_stroke);
         // context.set_source_rgba(*hex_to_rgba(gauge_frequency_bar_color))'
-        let color = self.hex_to_rgb(gauge_frequency_bar_color);
+        let color = util::hex_to_rgb(gauge_frequency_bar_color);
         context.set_source_rgb(color.0, color.1, color.2);
         context.arc(
             gauge_center.0,
@@ -589,7 +596,7 @@ impl Gauge {
         context.set_line_cap(cairo::LineCap::Butt);
 
         //SHADOW GAUGE
-        let color = self.hex_to_rgb(gauge_gauge_shadow_color);
+        let color = util::hex_to_rgb(gauge_gauge_shadow_color);
         context.set_source_rgba(color.0, color.1, color.2, 0.25);
 
         self.draw_gauge_tic(
@@ -616,7 +623,7 @@ impl Gauge {
         context.fill()?;
 
         //MAIN GAUGE
-        let color = self.hex_to_rgb(gauge_gauge_color);
+        let color = util::hex_to_rgb(gauge_gauge_color);
         context.set_source_rgb(color.0, color.1, color.2);
 
         self.draw_gauge_tic(
@@ -0,0 +1,253 @@
+/* history.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+
+use gtk::{cairo, gio, glib, glib::clone};
+
+use std::{cell::{Cell, RefCell}, collections::VecDeque, error::Error};
+use std::time::{Duration, Instant};
+
+use log::error;
+
+use super::tuning::NOTE_NAMES;
+use super::util;
+
+//semitones shown on the note axis
+const NOTE_SPAN: f64 = 12.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PitchSample {
+    pub cents: f64, //deviation from the target note
+    pub position: f64, //equal tempered semitones, midi numbering
+}
+
+mod imp {
+    use super::*;
+
+    #[derive(Debug)]
+    pub struct PitchHistory {
+        pub drawing_area: gtk::DrawingArea,
+        //None marks silence, drawn as a gap in the line
+        pub samples: RefCell<VecDeque<(Instant, Option<PitchSample>)>>,
+        pub window: Cell<f64>, //seconds of history shown
+        pub note_axis: Cell<bool>,
+        pub settings: gio::Settings,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for PitchHistory {
+        const NAME: &'static str = "PitchHistory";
+        type Type = super::PitchHistory;
+        type ParentType = adw::Bin;
+
+        fn new() -> Self {
+            Self {
+                drawing_area: gtk::DrawingArea::new(),
+                samples: RefCell::new(VecDeque::new()),
+                window: Cell::new(10.0),
+                note_axis: Cell::new(false),
+                settings: util::settings_manager(),
+            }
+        }
+    }
+
+    impl ObjectImpl for PitchHistory {
+        fn constructed(&self) {
+            self.parent_constructed();
+        }
+    }
+
+    impl WidgetImpl for PitchHistory {}
+    impl BinImpl for PitchHistory {}
+}
+
+glib::wrapper! {
+    pub struct PitchHistory(ObjectSubclass<imp::PitchHistory>)
+        @extends gtk::Widget, adw::Bin;
+}
+
+impl PitchHistory {
+    pub fn new() -> PitchHistory {
+        let object: PitchHistory = glib::Object::builder::<PitchHistory>().build();
+        object.construct();
+        object
+    }
+
+    fn construct(&self) {
+        let imp = self.imp();
+
+        self.set_hexpand(true);
+        self.set_vexpand(true);
+
+        imp.drawing_area.set_hexpand(true);
+        imp.drawing_area.set_vexpand(true);
+        imp.drawing_area
+            .set_draw_func(clone!(@strong self as this => move |_, context, _, _| {
+                match this.draw(context) {
+                    Ok(_) => (),
+                    Err(e) => {
+                        error!("{}", e);
+                    },
+                }
+            }));
+
+        //scroll with the frame clock so the graph moves during silence too
+        imp.drawing_area.add_tick_callback(
+            clone!(@weak self as this => @default-return glib::Continue(false), move |area, _| {
+                if area.is_mapped() {
+                    this.prune();
+                    area.queue_draw();
+                }
+                glib::Continue(true)
+            }),
+        );
+
+        self.set_child(Some(&imp.drawing_area));
+
+        self.retrieve_settings();
+        for key in ["history-window", "history-axis"] {
+            imp.settings.connect_changed(
+                Some(key),
+                clone!(@weak self as this => move |_settings, _name| {
+                    this.retrieve_settings();
+                }),
+            );
+        }
+    }
+
+    fn retrieve_settings(&self) {
+        let imp = self.imp();
+        imp.window.set(imp.settings.double("history-window"));
+        imp.note_axis.set(imp.settings.enum_("history-axis") == 1);
+    }
+
+    //record a detection, None for a silent frame
+    pub fn add_sample(&self, sample: Option<PitchSample>) {
+        let mut samples = self.imp().samples.borrow_mut();
+
+        //consecutive silent frames only need one marker
+        if sample.is_none() && matches!(samples.back(), Some((_, None))) {
+            return;
+        }
+
+        samples.push_back((Instant::now(), sample));
+    }
+
+    //forget samples that scrolled out of view
+    fn prune(&self) {
+        let imp = self.imp();
+        let window = Duration::from_secs_f64(imp.window.get());
+        let mut samples = imp.samples.borrow_mut();
+
+        while let Some((time, _)) = samples.front() {
+            if time.elapsed() > window {
+                samples.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn draw(&self, context: &cairo::Context) -> Result<(), Box<dyn Error>> {
+        let imp = self.imp();
+        let samples = imp.samples.borrow();
+
+        let line_color = "#364da5";
+        let grid_color = "#7486CC";
+        let target_color = "#ABE8FF";
+
+        let width = self.width() as f64;
+        let height = self.height() as f64;
+        let window = imp.window.get();
+        let now = Instant::now();
+
+        //vertical extent of the graph, either ±50 cents or an octave around the latest note
+        let (low, high) = match imp.note_axis.get() {
+            true => {
+                let latest = samples.iter().rev().find_map(|(_, s)| *s).map(|s| s.position).unwrap_or(69.0);
+                let center = latest.round();
+                (center - NOTE_SPAN / 2.0, center + NOTE_SPAN / 2.0)
+            }
+            false => (-50.0, 50.0),
+        };
+        let y = |value: f64| height * (1.0 - (value - low) / (high - low));
+        let x = |time: &Instant| width * (1.0 - now.duration_since(*time).as_secs_f64() / window);
+
+        //grid, labelled with note names on the note axis
+        let color = util::hex_to_rgb(grid_color);
+        context.set_source_rgba(color.0, color.1, color.2, 0.4);
+        context.set_line_width(1.0);
+        context.set_font_size(10.0);
+
+        if imp.note_axis.get() {
+            for step in low as i32..=high as i32 {
+                context.move_to(0.0, y(step as f64));
+                context.line_to(width, y(step as f64));
+                context.stroke()?;
+                context.move_to(2.0, y(step as f64) - 2.0);
+                context.show_text(&format!("{}{}", NOTE_NAMES[step.rem_euclid(12) as usize], step.div_euclid(12) - 1))?;
+            }
+        } else {
+            for cents in [-50, -25, 25, 50] {
+                context.move_to(0.0, y(cents as f64));
+                context.line_to(width, y(cents as f64));
+                context.stroke()?;
+                context.move_to(2.0, y(cents as f64) - 2.0);
+                context.show_text(&format!("{:+}", cents))?;
+            }
+
+            let color = util::hex_to_rgb(target_color);
+            context.set_source_rgb(color.0, color.1, color.2);
+            context.set_line_width(2.0);
+            context.move_to(0.0, y(0.0));
+            context.line_to(width, y(0.0));
+            context.stroke()?;
+        }
+
+        //the detected pitch, broken wherever nothing was heard
+        let color = util::hex_to_rgb(line_color);
+        context.set_source_rgb(color.0, color.1, color.2);
+        context.set_line_width(2.0);
+
+        let mut drawing = false;
+        for (time, sample) in samples.iter() {
+            let value = match (sample, imp.note_axis.get()) {
+                (Some(sample), true) => sample.position,
+                (Some(sample), false) => sample.cents.clamp(-50.0, 50.0),
+                (None, _) => {
+                    drawing = false;
+                    continue;
+                }
+            };
+
+            if drawing {
+                context.line_to(x(time), y(value));
+            } else {
+                context.move_to(x(time), y(value));
+                drawing = true;
+            }
+        }
+        context.stroke()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+/* instruments.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use gtk::{gio, glib};
+use gtk::prelude::*;
+
+use super::i18n::i18n;
+use super::tuning::{Note, NOTE_NAMES};
+
+//midi number of A4, the note the reference pitch is given for
+const REFERENCE_STEP: i32 = 69;
+
+#[derive(Clone, Debug)]
+pub struct InstrumentPreset {
+    pub id: String,
+    pub name: String,
+    pub strings: Vec<i32>, //midi numbers of the open strings, lowest course first
+    pub custom: bool,
+}
+
+impl InstrumentPreset {
+    fn builtin(id: &str, name: String, strings: &[&str]) -> InstrumentPreset {
+        InstrumentPreset {
+            id: id.to_string(),
+            name,
+            strings: strings.iter().map(|s| parse_note(s).unwrap()).collect(),
+            custom: false,
+        }
+    }
+
+    //find the string closest to frequency and measure against its open pitch
+    pub fn nearest_string(&self, frequency: f64, reference: f64, transposition: i32) -> Option<(usize, Note)> {
+        let position = REFERENCE_STEP as f64 + 12.0 * (frequency / reference).log2();
+
+        let (index, step) = self
+            .strings
+            .iter()
+            .enumerate()
+            .min_by(|a, b| (position - *a.1 as f64).abs().total_cmp(&(position - *b.1 as f64).abs()))?;
+
+        let written = step + transposition;
+
+        Some((index, Note {
+            name: NOTE_NAMES[written.rem_euclid(12) as usize].to_string(),
+            octave: written.div_euclid(12) - 1,
+            frequency: reference * 2.0_f64.powf((step - REFERENCE_STEP) as f64 / 12.0),
+            cents: 100.0 * (position - *step as f64),
+        }))
+    }
+
+    pub fn string_names(&self) -> Vec<String> {
+        self.strings.iter().map(|s| note_name(*s)).collect()
+    }
+}
+
+//note names like E2, F#3, Bb1 or C♯4 to midi numbers
+pub fn parse_note(name: &str) -> Option<i32> {
+    let mut chars = name.trim().chars().peekable();
+
+    let mut step = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+
+    while let Some(accidental) = chars.peek() {
+        match accidental {
+            '#' | '♯' => step += 1,
+            'b' | '♭' => step -= 1,
+            _ => break,
+        }
+        chars.next();
+    }
+
+    let octave = chars.collect::<String>().parse::<i32>().ok()?;
+    let midi = 12 * (octave + 1) + step;
+
+    match midi {
+        0..=127 => Some(midi),
+        _ => None,
+    }
+}
+
+pub fn note_name(midi: i32) -> String {
+    format!("{}{}", NOTE_NAMES[midi.rem_euclid(12) as usize], midi.div_euclid(12) - 1)
+}
+
+pub fn builtin_presets() -> Vec<InstrumentPreset> {
+    vec![
+        InstrumentPreset::builtin("guitar-standard", i18n("Guitar (Standard)"), &["E2", "A2", "D3", "G3", "B3", "E4"]),
+        InstrumentPreset::builtin("guitar-drop-d", i18n("Guitar (Drop D)"), &["D2", "A2", "D3", "G3", "B3", "E4"]),
+        InstrumentPreset::builtin("guitar-dadgad", i18n("Guitar (DADGAD)"), &["D2", "A2", "D3", "G3", "A3", "D4"]),
+        InstrumentPreset::builtin("guitar-open-g", i18n("Guitar (Open G)"), &["D2", "G2", "D3", "G3", "B3", "D4"]),
+        InstrumentPreset::builtin("bass-4", i18n("Bass (4 String)"), &["E1", "A1", "D2", "G2"]),
+        InstrumentPreset::builtin("bass-5", i18n("Bass (5 String)"), &["B0", "E1", "A1", "D2", "G2"]),
+        InstrumentPreset::builtin("bass-6", i18n("Bass (6 String)"), &["B0", "E1", "A1", "D2", "G2", "C3"]),
+        InstrumentPreset::builtin("violin", i18n("Violin"), &["G3", "D4", "A4", "E5"]),
+        InstrumentPreset::builtin("viola", i18n("Viola"), &["C3", "G3", "D4", "A4"]),
+        InstrumentPreset::builtin("cello", i18n("Cello"), &["C2", "G2", "D3", "A3"]),
+        InstrumentPreset::builtin("ukulele", i18n("Ukulele"), &["G4", "C4", "E4", "A4"]),
+        InstrumentPreset::builtin("mandolin", i18n("Mandolin"), &["G3", "D4", "A4", "E5"]),
+        InstrumentPreset::builtin("banjo", i18n("Banjo (Open G)"), &["G4", "D3", "G3", "B3", "D4"]),
+    ]
+}
+
+//user defined presets are stored as (name, [note names]) in custom-instrument-presets
+pub fn custom_presets(settings: &gio::Settings) -> Vec<InstrumentPreset> {
+    let stored = settings
+        .value("custom-instrument-presets")
+        .get::<Vec<(String, Vec<String>)>>()
+        .unwrap_or_default();
+
+    stored
+        .into_iter()
+        .map(|(name, strings)| InstrumentPreset {
+            id: format!("custom:{}", name),
+            name,
+            strings: strings.iter().filter_map(|s| parse_note(s)).collect(),
+            custom: true,
+        })
+        .collect()
+}
+
+pub fn save_custom_presets(settings: &gio::Settings, presets: &[InstrumentPreset]) -> Result<(), glib::BoolError> {
+    let stored: Vec<(String, Vec<String>)> = presets
+        .iter()
+        .filter(|p| p.custom)
+        .map(|p| (p.name.clone(), p.string_names()))
+        .collect();
+
+    settings.set_value("custom-instrument-presets", &stored.to_variant())
+}
+
+pub fn all_presets(settings: &gio::Settings) -> Vec<InstrumentPreset> {
+    let mut presets = builtin_presets();
+    presets.extend(custom_presets(settings));
+    presets
+}
+
+//the preset chosen in instrument-preset, None for chromatic tuning
+pub fn selected_preset(settings: &gio::Settings) -> Option<InstrumentPreset> {
+    let id = settings.string("instrument-preset").to_string();
+    all_presets(settings).into_iter().find(|p| p.id == id)
+}
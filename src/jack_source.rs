@@ -0,0 +1,216 @@
+/* jack_source.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//jack client mode, built with the jack feature. chromatic registers an input
+//port, and optionally an output it copies the input to, and the devices are
+//the output ports of other clients the input can be connected to
+
+use gtk::glib::Sender;
+
+use std::{error::Error, fmt, mem, slice, thread};
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+use std::time::Duration;
+use log::{debug, error, warn};
+
+use super::audio_source::{AudioSource, AudioSourceError, InputDevice};
+use super::recorder::{Analyser, AnalysisSettings};
+use super::window::AudioAction;
+
+const CLIENT_NAME: &str = "Chromatic";
+const AUDIO_TYPE: &str = "32 bit float mono audio";
+//periods are often too short to find low pitches in, so whole periods are
+//gathered until at least this many samples are analysed at once
+const MIN_ANALYSIS_SAMPLES: u32 = 2048;
+//analysis buffers the ring buffer holds before periods are dropped
+const RING_BUFFERS: usize = 4;
+
+fn open_client(name: &str) -> Result<jack::Client, Box<dyn Error>> {
+    let (client, status) = jack::Client::new(name, jack::ClientOptions::NO_START_SERVER)?;
+    debug!("jack -> client {} {:?}", client.name(), status);
+    Ok(client)
+}
+
+fn output_ports(client: &jack::Client, flags: jack::PortFlags) -> Vec<String> {
+    client.ports(None, Some(AUDIO_TYPE), jack::PortFlags::IS_OUTPUT | flags)
+}
+
+//the ring buffer takes bytes, viewing the samples as bytes avoids a copy
+fn as_bytes(samples: &[f32]) -> &[u8] {
+    unsafe { slice::from_raw_parts(samples.as_ptr() as *const u8, mem::size_of_val(samples)) }
+}
+
+//runs on the jack realtime thread, so it must neither allocate nor block. the
+//period is only copied into a preallocated lock free ring buffer
+struct Capture {
+    input: jack::Port<jack::AudioIn>,
+    output: Option<jack::Port<jack::AudioOut>>,
+    writer: jack::RingBufferWriter,
+}
+
+impl jack::ProcessHandler for Capture {
+    fn process(&mut self, _: &jack::Client, scope: &jack::ProcessScope) -> jack::Control {
+        let input = self.input.as_slice(scope);
+
+        if let Some(output) = self.output.as_mut() {
+            output.as_mut_slice(scope).copy_from_slice(input);
+        }
+
+        //a full ring means the analysis fell behind, drop the whole period
+        //rather than leave a gap inside one
+        let bytes = as_bytes(input);
+        if self.writer.space() >= bytes.len() {
+            self.writer.write_buffer(bytes);
+        }
+
+        jack::Control::Continue
+    }
+}
+
+pub struct JackSource {
+    passthrough: bool,
+    client: Option<jack::AsyncClient<(), Capture>>,
+    //cleared to end the analysis thread
+    running: Option<Arc<AtomicBool>>,
+}
+
+impl fmt::Debug for JackSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JackSource")
+            .field("passthrough", &self.passthrough)
+            .field("running", &self.client.is_some())
+            .finish()
+    }
+}
+
+impl JackSource {
+    //with passthrough the input is copied to an output port
+    pub fn new(passthrough: bool) -> JackSource {
+        JackSource {
+            passthrough,
+            client: None,
+            running: None,
+        }
+    }
+}
+
+impl AudioSource for JackSource {
+    fn devices(&self) -> Result<Vec<InputDevice>, Box<dyn Error>> {
+        let ports = match self.client.as_ref() {
+            Some(client) => output_ports(client.as_client(), jack::PortFlags::empty()),
+            None => output_ports(&open_client(&format!("{}-ports", CLIENT_NAME))?, jack::PortFlags::empty()),
+        };
+
+        //our own passthrough output would only feed back into the input
+        Ok(ports
+            .iter()
+            .filter(|port| !port.starts_with(&format!("{}:", CLIENT_NAME)))
+            .map(|port| InputDevice::named(port))
+            .collect())
+    }
+
+    //the server's sample rate and period are used, the buffer-size setting is not
+    fn start(&mut self, device: Option<&InputDevice>, mut analysis: AnalysisSettings, glib_sender: Sender<AudioAction>) -> Result<(), Box<dyn Error>> {
+        self.stop();
+
+        let client = open_client(CLIENT_NAME)?;
+        let input = client.register_port("in", jack::AudioIn::default())?;
+        let output = match self.passthrough {
+            true => Some(client.register_port("out", jack::AudioOut::default())?),
+            false => None,
+        };
+
+        let sample_rate = client.sample_rate() as f64;
+        let period = client.buffer_size();
+        analysis.buffer_size = period * ((MIN_ANALYSIS_SAMPLES + period - 1) / period);
+        debug!("jack -> {} Hz, period {}, analysing {}", sample_rate, period, analysis.buffer_size);
+
+        let source_port = match device {
+            Some(device) => device.id.clone(),
+            None => output_ports(&client, jack::PortFlags::IS_PHYSICAL)
+                .into_iter()
+                .next()
+                .ok_or_else(|| AudioSourceError("no capture ports".into()))?,
+        };
+        let input_name = input.name()?;
+
+        let buffer_size = analysis.buffer_size as usize;
+        let ring = jack::RingBuffer::new(RING_BUFFERS * buffer_size * mem::size_of::<f32>())?;
+        let (mut reader, writer) = ring.into_reader_writer();
+
+        let active = client.activate_async((), Capture { input, output, writer })?;
+        active.as_client().connect_ports_by_name(&source_port, &input_name)?;
+        debug!("jack -> connected {} to {}", source_port, input_name);
+
+        let running = Arc::new(AtomicBool::new(true));
+        let period_duration = Duration::from_secs_f64(period as f64 / sample_rate);
+
+        thread::spawn({
+            let running = running.clone();
+            move || {
+                let mut analyser = match Analyser::new(analysis, sample_rate, glib_sender) {
+                    Ok(analyser) => analyser,
+                    Err(e) => {
+                        error!("jack -> unable to create analyser: {}", e);
+                        return;
+                    }
+                };
+                let mut bytes = vec![0_u8; buffer_size * mem::size_of::<f32>()];
+                let mut samples = vec![0.0_f32; buffer_size];
+
+                while running.load(Ordering::Relaxed) {
+                    //the writer only ever adds whole periods, and buffer_size is a
+                    //multiple of the period, so a full buffer is always aligned
+                    if reader.space() < bytes.len() {
+                        thread::sleep(period_duration);
+                        continue;
+                    }
+
+                    let read = reader.read_buffer(&mut bytes);
+                    if read != bytes.len() {
+                        warn!("jack -> short read of {} bytes", read);
+                        continue;
+                    }
+
+                    for (sample, b) in samples.iter_mut().zip(bytes.chunks_exact(4)) {
+                        *sample = f32::from_ne_bytes([b[0], b[1], b[2], b[3]]);
+                    }
+                    analyser.process(&samples);
+                }
+
+                debug!("jack -> analysis closing ...");
+            }
+        });
+
+        self.client = Some(active);
+        self.running = Some(running);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(running) = self.running.take() {
+            running.store(false, Ordering::Relaxed);
+        }
+        if let Some(client) = self.client.take() {
+            if let Err(e) = client.deactivate() {
+                error!("jack -> unable to deactivate client: {}", e);
+            }
+        }
+    }
+}
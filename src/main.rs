@@ -24,9 +24,30 @@ mod window;
 mod i18n;
 mod recorder;
 mod gauge;
+mod strobe;
+mod history;
+mod spectrum;
+mod oscilloscope;
+mod tone_generator;
+mod cli;
+mod file_source;
+mod pitch_log;
+mod dbus;
+mod osc;
+mod melody;
+mod audio_source;
+mod portaudio_source;
+mod synthetic_source;
+#[cfg(feature = "pipewire")]
+mod pipewire_source;
+#[cfg(feature = "jack")]
+mod jack_source;
 mod util;
 mod preferences_window;
 mod toasts;
+mod tuning;
+mod scala;
+mod instruments;
 
 use self::application::App;
 use self::window::Window;
@@ -48,6 +69,12 @@ fn main() {
         .expect("Unable to set the text domain encoding");
     textdomain(GETTEXT_PACKAGE).expect("Unable to switch to the text domain");
 
+    // The terminal mode runs the recorder on its own, without any window
+    let args: Vec<String> = env::args().collect();
+    if cli::wants_cli(&args) {
+        process::exit(cli::run(&args));
+    }
+
     // Load resources
     debug!("Loading resources");
     let resources = match env::var("MESON_DEVENV") {
@@ -72,7 +99,7 @@ fn main() {
     // Create a new GtkApplication. The application manages our main loop,
     // application windows, integration with the window manager/compositor, and
     // desktop features such as file opening and single-instance applications.
-    let app = App::new("io.github.nate_xyz.Chromatic", &gio::ApplicationFlags::empty());
+    let app = App::new("io.github.nate_xyz.Chromatic", &gio::ApplicationFlags::HANDLES_OPEN);
 
     // Run the application. This function will block until the application
     // exits. Upon return, we have our exit code to return to the shell. (This
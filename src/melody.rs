@@ -0,0 +1,320 @@
+/* melody.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//segments the detected pitches into notes and writes them as a standard midi file
+
+use std::{error::Error, fs::File, io::Write, path::Path};
+
+//notes shorter than this are most likely glitches of the detector
+const MIN_NOTE_SECONDS: f64 = 0.08;
+//semitones the pitch may wander from a note before a new note starts,
+//a bit more than half so a wide vibrato does not split notes
+const NOTE_HYSTERESIS: f64 = 0.7;
+//the default pitch bend range of general midi, in semitones
+const BEND_RANGE: f64 = 2.0;
+
+const TICKS_PER_QUARTER: u16 = 480;
+const MICROSECONDS_PER_QUARTER: u32 = 500_000; //120 bpm
+const VELOCITY: u8 = 100;
+
+#[derive(Clone, Debug)]
+pub struct MelodyNote {
+    pub onset: f64, //seconds from the start of the recording
+    pub offset: f64,
+    pub midi: u8,
+    pub cents: f64, //mean deviation from the midi note
+}
+
+#[derive(Debug)]
+struct OpenNote {
+    onset: f64,
+    last: f64,
+    midi: i32,
+    deviations: Vec<f64>, //semitones from midi, per frame
+}
+
+#[derive(Debug)]
+pub struct MelodyRecorder {
+    //seconds of audio analysed so far. counted from the frames rather than the
+    //clock, files are analysed faster than real time
+    time: f64,
+    reference: f64,
+    current: Option<OpenNote>,
+    notes: Vec<MelodyNote>,
+}
+
+impl MelodyRecorder {
+    //reference is the frequency of A4, midi note 69
+    pub fn new(reference: f64) -> MelodyRecorder {
+        MelodyRecorder {
+            time: 0.0,
+            reference,
+            current: None,
+            notes: Vec::new(),
+        }
+    }
+
+    //one analysed frame of duration seconds, None when nothing is heard
+    pub fn add_frame(&mut self, duration: f64, frequency: Option<f64>) {
+        let time = self.time;
+        self.time += duration;
+
+        let position = match frequency {
+            Some(frequency) if frequency > 0.0 => 69.0 + 12.0 * (frequency / self.reference).log2(),
+            _ => {
+                self.close_note();
+                return;
+            }
+        };
+
+        let same_note = match self.current.as_ref() {
+            Some(note) => (position - note.midi as f64).abs() < NOTE_HYSTERESIS,
+            None => false,
+        };
+
+        if !same_note {
+            self.close_note();
+            let midi = position.round() as i32;
+            if !(0..=127).contains(&midi) {
+                return;
+            }
+            self.current = Some(OpenNote {
+                onset: time,
+                last: time,
+                midi,
+                deviations: Vec::new(),
+            });
+        }
+
+        //the note lasts to the end of the frame
+        let note = self.current.as_mut().unwrap();
+        note.last = self.time;
+        note.deviations.push(position - note.midi as f64);
+    }
+
+    fn close_note(&mut self) {
+        let note = match self.current.take() {
+            Some(note) => note,
+            None => return,
+        };
+
+        if note.last - note.onset < MIN_NOTE_SECONDS {
+            return;
+        }
+
+        let mean = note.deviations.iter().sum::<f64>() / note.deviations.len() as f64;
+        self.notes.push(MelodyNote {
+            onset: note.onset,
+            offset: note.last,
+            midi: note.midi as u8,
+            cents: mean * 100.0,
+        });
+    }
+
+    //end the recording, returning the notes heard
+    pub fn finish(mut self) -> Vec<MelodyNote> {
+        self.close_note();
+        self.notes
+    }
+}
+
+fn push_variable_length(data: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+}
+
+fn ticks(seconds: f64) -> u32 {
+    let ticks_per_second = TICKS_PER_QUARTER as f64 * 1_000_000.0 / MICROSECONDS_PER_QUARTER as f64;
+    (seconds * ticks_per_second).round() as u32
+}
+
+//14 bit pitch bend value for a deviation in cents, 8192 is no bend
+fn pitch_bend(cents: f64) -> (u8, u8) {
+    let value = (8192.0 + cents / (BEND_RANGE * 100.0) * 8192.0).round().clamp(0.0, 16383.0) as u16;
+    ((value & 0x7f) as u8, (value >> 7) as u8)
+}
+
+//a format 0 standard midi file, each note preceded by the pitch bend of its deviation
+pub fn write_midi(notes: &[MelodyNote], path: &Path) -> Result<(), Box<dyn Error>> {
+    File::create(path)?.write_all(&midi_file(notes))?;
+    Ok(())
+}
+
+fn midi_file(notes: &[MelodyNote]) -> Vec<u8> {
+    //(tick, event bytes)
+    let mut events: Vec<(u32, Vec<u8>)> = Vec::new();
+
+    let tempo = MICROSECONDS_PER_QUARTER.to_be_bytes();
+    events.push((0, vec![0xff, 0x51, 0x03, tempo[1], tempo[2], tempo[3]]));
+    //set the pitch bend range, registered parameter 0
+    events.push((0, vec![0xb0, 101, 0]));
+    events.push((0, vec![0xb0, 100, 0]));
+    events.push((0, vec![0xb0, 6, BEND_RANGE as u8]));
+    events.push((0, vec![0xb0, 38, 0]));
+
+    for note in notes {
+        let (lsb, msb) = pitch_bend(note.cents);
+        let on = ticks(note.onset);
+        let off = ticks(note.offset).max(on + 1);
+        events.push((on, vec![0xe0, lsb, msb]));
+        events.push((on, vec![0x90, note.midi, VELOCITY]));
+        events.push((off, vec![0x80, note.midi, 0]));
+    }
+
+    //where one note ends as the next starts the note off goes first, the sort
+    //is stable so other events keep their order
+    events.sort_by_key(|(tick, event)| (*tick, event[0] != 0x80));
+
+    let mut track = Vec::new();
+    let mut previous = 0;
+    for (tick, event) in events {
+        push_variable_length(&mut track, tick - previous);
+        track.extend(event);
+        previous = tick;
+    }
+    track.extend([0x00, 0xff, 0x2f, 0x00]); //end of track
+
+    let mut data = Vec::with_capacity(track.len() + 22);
+    data.extend(b"MThd");
+    data.extend(6u32.to_be_bytes());
+    data.extend(0u16.to_be_bytes()); //format 0, a single track
+    data.extend(1u16.to_be_bytes());
+    data.extend(TICKS_PER_QUARTER.to_be_bytes());
+    data.extend(b"MTrk");
+    data.extend((track.len() as u32).to_be_bytes());
+    data.extend(track);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variable_length(value: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        push_variable_length(&mut data, value);
+        data
+    }
+
+    fn note(onset: f64, offset: f64, midi: u8, cents: f64) -> MelodyNote {
+        MelodyNote { onset, offset, midi, cents }
+    }
+
+    #[test]
+    fn test_variable_length() {
+        assert_eq!(variable_length(0), [0x00]);
+        assert_eq!(variable_length(0x40), [0x40]);
+        assert_eq!(variable_length(0x7f), [0x7f]);
+        assert_eq!(variable_length(0x80), [0x81, 0x00]);
+        assert_eq!(variable_length(0x2000), [0xc0, 0x00]);
+        assert_eq!(variable_length(0x3fff), [0xff, 0x7f]);
+        assert_eq!(variable_length(0x4000), [0x81, 0x80, 0x00]);
+        assert_eq!(variable_length(0x0fff_ffff), [0xff, 0xff, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn test_pitch_bend() {
+        assert_eq!(pitch_bend(0.0), (0x00, 0x40));
+        //a semitone up is half the range of two
+        assert_eq!(pitch_bend(100.0), (0x00, 0x60));
+        assert_eq!(pitch_bend(-100.0), (0x00, 0x20));
+        assert_eq!(pitch_bend(1.0), (0x29, 0x40));
+        //beyond the range is clamped
+        assert_eq!(pitch_bend(200.0), (0x7f, 0x7f));
+        assert_eq!(pitch_bend(-500.0), (0x00, 0x00));
+    }
+
+    #[test]
+    fn test_ticks() {
+        //480 ticks per quarter at 120 bpm
+        assert_eq!(ticks(0.0), 0);
+        assert_eq!(ticks(0.5), 480);
+        assert_eq!(ticks(1.0), 960);
+    }
+
+    #[test]
+    fn test_midi_file() {
+        let data = midi_file(&[note(0.5, 1.0, 69, 100.0), note(1.0, 1.5, 71, 0.0)]);
+
+        //header: format 0, one track, 480 ticks per quarter
+        assert_eq!(&data[..14], [b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0x01, 0xe0]);
+        assert_eq!(&data[14..18], b"MTrk");
+        let length = u32::from_be_bytes([data[18], data[19], data[20], data[21]]) as usize;
+        assert_eq!(data.len(), 22 + length);
+
+        #[rustfmt::skip]
+        let track: Vec<u8> = vec![
+            //tempo, 500000 microseconds per quarter
+            0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20,
+            //pitch bend range of two semitones
+            0x00, 0xb0, 101, 0,
+            0x00, 0xb0, 100, 0,
+            0x00, 0xb0, 6, 2,
+            0x00, 0xb0, 38, 0,
+            //480 ticks later, a semitone bend and A4
+            0x83, 0x60, 0xe0, 0x00, 0x60,
+            0x00, 0x90, 69, 100,
+            //480 ticks later A4 ends before B4 starts unbent
+            0x83, 0x60, 0x80, 69, 0,
+            0x00, 0xe0, 0x00, 0x40,
+            0x00, 0x90, 71, 100,
+            0x83, 0x60, 0x80, 71, 0,
+            //end of track
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        assert_eq!(&data[22..], track);
+    }
+
+    #[test]
+    fn test_segmentation() {
+        let frame = 0.05;
+        let mut recorder = MelodyRecorder::new(440.0);
+
+        //A4 a little sharp, with a vibrato inside the hysteresis
+        for i in 0..10 {
+            let cents = if i % 2 == 0 { 30.0 } else { -10.0 };
+            recorder.add_frame(frame, Some(440.0 * 2.0_f64.powf(cents / 1200.0)));
+        }
+        //a glitch too short to be a note, then silence
+        recorder.add_frame(frame, Some(1000.0));
+        recorder.add_frame(frame, None);
+        //C5 until the end
+        for _ in 0..4 {
+            recorder.add_frame(frame, Some(523.25));
+        }
+
+        let notes = recorder.finish();
+        assert_eq!(notes.len(), 2);
+
+        assert_eq!(notes[0].midi, 69);
+        assert!(notes[0].onset.abs() < 1e-9);
+        assert!((notes[0].offset - 0.5).abs() < 1e-9);
+        assert!((notes[0].cents - 10.0).abs() < 1e-6);
+
+        assert_eq!(notes[1].midi, 72);
+        assert!((notes[1].onset - 0.6).abs() < 1e-9);
+        assert!((notes[1].offset - 0.8).abs() < 1e-9);
+    }
+}
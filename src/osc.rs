@@ -0,0 +1,181 @@
+/* osc.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//open sound control output over udp, for supercollider, pure data and the like.
+//every detected pitch is sent as
+//  /chromatic/pitch f   frequency in Hz
+//  /chromatic/note  s i name and octave
+//  /chromatic/cents f   deviation from the note
+
+use std::{error::Error, fmt, io};
+use std::net::{ToSocketAddrs, UdpSocket};
+use log::debug;
+
+use super::tuning::Note;
+
+#[derive(Debug)]
+struct OscError(String);
+
+impl fmt::Display for OscError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for OscError {}
+
+enum Argument<'a> {
+    Float(f32),
+    Int(i32),
+    Str(&'a str),
+}
+
+//osc strings are null terminated and padded to a multiple of four bytes
+fn push_string(packet: &mut Vec<u8>, text: &str) {
+    packet.extend_from_slice(text.as_bytes());
+    let padding = 4 - text.len() % 4;
+    packet.resize(packet.len() + padding, 0);
+}
+
+fn message(address: &str, arguments: &[Argument]) -> Vec<u8> {
+    let tags: String = std::iter::once(',')
+        .chain(arguments.iter().map(|a| match a {
+            Argument::Float(_) => 'f',
+            Argument::Int(_) => 'i',
+            Argument::Str(_) => 's',
+        }))
+        .collect();
+
+    let mut packet = Vec::with_capacity(64);
+    push_string(&mut packet, address);
+    push_string(&mut packet, &tags);
+
+    for argument in arguments {
+        match argument {
+            Argument::Float(value) => packet.extend_from_slice(&value.to_be_bytes()),
+            Argument::Int(value) => packet.extend_from_slice(&value.to_be_bytes()),
+            Argument::Str(value) => push_string(&mut packet, value),
+        }
+    }
+
+    packet
+}
+
+#[derive(Debug)]
+pub struct OscSender {
+    socket: UdpSocket,
+}
+
+impl OscSender {
+    pub fn new(host: &str, port: u16) -> Result<OscSender, Box<dyn Error>> {
+        let target = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| OscError(format!("unable to resolve {}", host)))?;
+
+        let local = match target.is_ipv6() {
+            true => "[::]:0",
+            false => "0.0.0.0:0",
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(target)?;
+
+        debug!("osc -> sending to {}", target);
+        Ok(OscSender { socket })
+    }
+
+    pub fn send_pitch(&self, frequency: f32, note: &Note) -> io::Result<()> {
+        let messages = [
+            message("/chromatic/pitch", &[Argument::Float(frequency)]),
+            message("/chromatic/note", &[Argument::Str(&note.name), Argument::Int(note.octave)]),
+            message("/chromatic/cents", &[Argument::Float(note.cents as f32)]),
+        ];
+
+        for packet in messages.iter() {
+            self.socket.send(packet)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(text: &str) -> Vec<u8> {
+        let mut packet = Vec::new();
+        push_string(&mut packet, text);
+        packet
+    }
+
+    #[test]
+    fn test_push_string() {
+        //at least one null, padded to four bytes
+        assert_eq!(string(""), b"\0\0\0\0");
+        assert_eq!(string("abc"), b"abc\0");
+        assert_eq!(string("abcd"), b"abcd\0\0\0\0");
+        assert_eq!(string(",f"), b",f\0\0");
+    }
+
+    #[test]
+    fn test_message() {
+        let packet = message("/chromatic/pitch", &[Argument::Float(440.0)]);
+        let mut expected = b"/chromatic/pitch\0\0\0\0,f\0\0".to_vec();
+        expected.extend_from_slice(&[0x43, 0xdc, 0x00, 0x00]);
+        assert_eq!(packet, expected);
+
+        let packet = message("/chromatic/note", &[Argument::Str("C♯"), Argument::Int(-1)]);
+        let mut expected = b"/chromatic/note\0,si\0".to_vec();
+        //four bytes of utf-8, so a whole word of padding
+        expected.extend_from_slice("C♯".as_bytes());
+        expected.extend_from_slice(&[0, 0, 0, 0]);
+        expected.extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(packet, expected);
+        assert_eq!(packet.len() % 4, 0);
+
+        assert_eq!(message("/empty", &[]), b"/empty\0\0,\0\0\0");
+    }
+
+    #[test]
+    fn test_send_pitch() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+        let port = receiver.local_addr().unwrap().port();
+
+        let note = Note {
+            name: "A".to_string(),
+            octave: 4,
+            frequency: 440.0,
+            cents: 1.5,
+        };
+        OscSender::new("127.0.0.1", port).unwrap().send_pitch(441.0, &note).unwrap();
+
+        let mut buffer = [0; 64];
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let length = receiver.recv(&mut buffer).unwrap();
+            received.push(buffer[..length].to_vec());
+        }
+
+        assert_eq!(received[0], message("/chromatic/pitch", &[Argument::Float(441.0)]));
+        assert_eq!(received[1], message("/chromatic/note", &[Argument::Str("A"), Argument::Int(4)]));
+        assert_eq!(received[2], message("/chromatic/cents", &[Argument::Float(1.5)]));
+    }
+}
@@ -0,0 +1,200 @@
+/* oscilloscope.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+
+use gtk::{cairo, glib, glib::clone};
+
+use std::{cell::{Cell, RefCell}, error::Error};
+
+use log::error;
+
+use super::util;
+
+//periods of the detected pitch shown at once
+const PERIODS: f64 = 3.0;
+//time shown when there is no pitch to trigger on
+const FREE_RUN_SECONDS: f64 = 0.02;
+//samples at or beyond this magnitude are counted as clipped
+const CLIP_LEVEL: f32 = 0.999;
+
+//first rising crossing of level within the first period, so consecutive buffers
+//start at the same phase of the wave
+fn trigger_index(buffer: &[f32], level: f32, period: usize) -> usize {
+    let search = period.min(buffer.len().saturating_sub(1));
+    (0..search)
+        .find(|i| buffer[*i] < level && buffer[*i + 1] >= level)
+        .unwrap_or(0)
+}
+
+mod imp {
+    use super::*;
+
+    #[derive(Debug)]
+    pub struct Oscilloscope {
+        pub drawing_area: gtk::DrawingArea,
+        pub buffer: RefCell<Vec<f32>>,
+        pub sample_rate: Cell<f64>,
+        pub frequency: Cell<Option<f64>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for Oscilloscope {
+        const NAME: &'static str = "Oscilloscope";
+        type Type = super::Oscilloscope;
+        type ParentType = adw::Bin;
+
+        fn new() -> Self {
+            Self {
+                drawing_area: gtk::DrawingArea::new(),
+                buffer: RefCell::new(Vec::new()),
+                sample_rate: Cell::new(44100.0),
+                frequency: Cell::new(None),
+            }
+        }
+    }
+
+    impl ObjectImpl for Oscilloscope {
+        fn constructed(&self) {
+            self.parent_constructed();
+        }
+    }
+
+    impl WidgetImpl for Oscilloscope {}
+    impl BinImpl for Oscilloscope {}
+}
+
+glib::wrapper! {
+    pub struct Oscilloscope(ObjectSubclass<imp::Oscilloscope>)
+        @extends gtk::Widget, adw::Bin;
+}
+
+impl Oscilloscope {
+    pub fn new() -> Oscilloscope {
+        let object: Oscilloscope = glib::Object::builder::<Oscilloscope>().build();
+        object.construct();
+        object
+    }
+
+    fn construct(&self) {
+        let imp = self.imp();
+
+        self.set_hexpand(true);
+        self.set_vexpand(true);
+        self.set_halign(gtk::Align::Fill);
+        self.set_valign(gtk::Align::Fill);
+
+        imp.drawing_area.set_hexpand(true);
+        imp.drawing_area.set_vexpand(true);
+        imp.drawing_area
+            .set_draw_func(clone!(@strong self as this => move |_, context, _, _| {
+                match this.draw(context) {
+                    Ok(_) => (),
+                    Err(e) => {
+                        error!("{}", e);
+                    },
+                }
+            }));
+
+        self.set_child(Some(&imp.drawing_area));
+    }
+
+    pub fn set_buffer(&self, buffer: &[f32], sample_rate: f64) {
+        let imp = self.imp();
+        imp.buffer.replace(buffer.to_vec());
+        imp.sample_rate.set(sample_rate);
+        imp.drawing_area.queue_draw();
+    }
+
+    //the detected pitch, used to trigger and scale the trace. None when nothing is heard
+    pub fn set_frequency(&self, frequency: Option<f64>) {
+        self.imp().frequency.set(frequency);
+    }
+
+    fn draw(&self, context: &cairo::Context) -> Result<(), Box<dyn Error>> {
+        let imp = self.imp();
+        let buffer = imp.buffer.borrow();
+
+        let trace_color = "#364da5";
+        let grid_color = "#7486CC";
+        let offset_color = "#ABE8FF";
+        let clip_color = "#e01b24";
+
+        let width = self.width() as f64;
+        let height = self.height() as f64;
+        let y = |sample: f64| height / 2.0 * (1.0 - sample.clamp(-1.0, 1.0) * 0.9);
+
+        //centre line and full scale limits
+        let color = util::hex_to_rgb(grid_color);
+        context.set_source_rgba(color.0, color.1, color.2, 0.4);
+        context.set_line_width(1.0);
+        for level in [-1.0, 0.0, 1.0] {
+            context.move_to(0.0, y(level));
+            context.line_to(width, y(level));
+            context.stroke()?;
+        }
+
+        if buffer.len() < 2 {
+            return Ok(());
+        }
+
+        let sample_rate = imp.sample_rate.get();
+        let mean = buffer.iter().sum::<f32>() / buffer.len() as f32;
+        let clipped = buffer.iter().any(|s| s.abs() >= CLIP_LEVEL);
+
+        //a dc offset shows as the mean drifting away from the centre line
+        let color = util::hex_to_rgb(offset_color);
+        context.set_source_rgb(color.0, color.1, color.2);
+        context.set_dash(&[4.0, 4.0], 0.0);
+        context.move_to(0.0, y(mean as f64));
+        context.line_to(width, y(mean as f64));
+        context.stroke()?;
+        context.set_dash(&[], 0.0);
+
+        let (start, shown) = match imp.frequency.get() {
+            Some(frequency) if frequency > 0.0 => {
+                let period = (sample_rate / frequency).round() as usize;
+                (trigger_index(&buffer, mean, period), (PERIODS * sample_rate / frequency) as usize)
+            }
+            _ => (0, (FREE_RUN_SECONDS * sample_rate) as usize),
+        };
+        let shown = shown.clamp(2, buffer.len() - start);
+
+        let color = match clipped {
+            true => util::hex_to_rgb(clip_color),
+            false => util::hex_to_rgb(trace_color),
+        };
+        context.set_source_rgb(color.0, color.1, color.2);
+        context.set_line_width(2.0);
+
+        for (i, sample) in buffer[start..start + shown].iter().enumerate() {
+            let x = width * i as f64 / (shown - 1) as f64;
+            if i == 0 {
+                context.move_to(x, y(*sample as f64));
+            } else {
+                context.line_to(x, y(*sample as f64));
+            }
+        }
+        context.stroke()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+/* pipewire_source.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//native pipewire capture, built with the pipewire feature. nodes are targeted by
+//their node.name, which stays the same across restarts unlike the object id,
+//so no fuzzy matching against portaudio names is needed
+
+use gtk::glib::Sender;
+
+use std::{cell::RefCell, error::Error, fmt, io::Cursor, rc::Rc, thread};
+use std::sync::mpsc;
+use log::{debug, error};
+
+use pipewire as pw;
+use pw::{properties, spa};
+use spa::param::audio::{AudioFormat, AudioInfoRaw};
+use spa::pod::{serialize::PodSerializer, Object, Pod, Value};
+
+use super::audio_source::{AudioSource, InputDevice};
+use super::recorder::{Analyser, AnalysisSettings};
+use super::window::AudioAction;
+
+//pipewire resamples to whatever rate is asked for
+const SAMPLE_RATE: u32 = 48000;
+
+#[derive(Debug)]
+struct PipeWireError(String);
+
+impl fmt::Display for PipeWireError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for PipeWireError {}
+
+//audio sources currently known to the pipewire server, by node.name and node.description
+fn input_nodes() -> Result<Vec<InputDevice>, Box<dyn Error>> {
+    pw::init();
+
+    let mainloop = pw::MainLoop::new()?;
+    let context = pw::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+    let registry = core.get_registry()?;
+
+    let nodes = Rc::new(RefCell::new(Vec::new()));
+
+    let _registry_listener = registry
+        .add_listener_local()
+        .global({
+            let nodes = nodes.clone();
+            move |global| {
+                if global.type_ != pw::types::ObjectType::Node {
+                    return;
+                }
+                let props = match global.props.as_ref() {
+                    Some(props) => props,
+                    None => return,
+                };
+                if props.get("media.class") != Some("Audio/Source") {
+                    return;
+                }
+                if let Some(name) = props.get("node.name") {
+                    let description = props.get("node.description").unwrap_or(name);
+                    nodes.borrow_mut().push(InputDevice {
+                        id: name.to_string(),
+                        name: description.to_string(),
+                    });
+                }
+            }
+        })
+        .register();
+
+    //every global has been announced once the server answers the sync
+    let pending = core.sync(0)?;
+    let _core_listener = core
+        .add_listener_local()
+        .done({
+            let mainloop = mainloop.clone();
+            move |id, seq| {
+                if id == pw::PW_ID_CORE && seq == pending {
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+
+    mainloop.run();
+
+    let nodes = nodes.borrow().clone();
+    Ok(nodes)
+}
+
+//a running capture stream. dropping it ends the stream and its thread
+#[derive(Debug)]
+struct PipeWireCapture {
+    quit: pw::channel::Sender<()>,
+}
+
+impl PipeWireCapture {
+    //node is a node.name, None lets the session manager pick the default source
+    fn start(node: Option<String>, analysis: AnalysisSettings, sender: Sender<AudioAction>) -> Result<PipeWireCapture, Box<dyn Error>> {
+        let (quit, quit_receiver) = pw::channel::channel::<()>();
+        //the thread reports whether the stream could be set up
+        let (ready, ready_receiver) = mpsc::channel::<Result<(), String>>();
+
+        thread::spawn(move || {
+            if let Err(e) = run(node, analysis, sender, quit_receiver, &ready) {
+                error!("pipewire capture error: {}", e);
+                let _ = ready.send(Err(e.to_string()));
+            }
+            debug!("pipewire capture closing ...");
+        });
+
+        match ready_receiver.recv() {
+            Ok(Ok(())) => Ok(PipeWireCapture { quit }),
+            Ok(Err(e)) => Err(Box::new(PipeWireError(e))),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
+
+impl Drop for PipeWireCapture {
+    fn drop(&mut self) {
+        let _ = self.quit.send(());
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PipeWireSource {
+    capture: Option<PipeWireCapture>,
+}
+
+impl PipeWireSource {
+    pub fn new() -> PipeWireSource {
+        PipeWireSource::default()
+    }
+}
+
+impl AudioSource for PipeWireSource {
+    fn devices(&self) -> Result<Vec<InputDevice>, Box<dyn Error>> {
+        input_nodes()
+    }
+
+    fn start(&mut self, device: Option<&InputDevice>, analysis: AnalysisSettings, sender: Sender<AudioAction>) -> Result<(), Box<dyn Error>> {
+        //end the previous capture first, so both never run at once
+        self.capture = None;
+        self.capture = Some(PipeWireCapture::start(device.map(|d| d.id.clone()), analysis, sender)?);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.capture = None;
+    }
+}
+
+fn format_params() -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut info = AudioInfoRaw::new();
+    info.set_format(AudioFormat::F32LE);
+    info.set_rate(SAMPLE_RATE);
+    info.set_channels(1);
+
+    let object = Value::Object(Object {
+        type_: spa::sys::SPA_TYPE_OBJECT_Format,
+        id: spa::sys::SPA_PARAM_EnumFormat,
+        properties: info.into(),
+    });
+
+    let (cursor, _) = PodSerializer::serialize(Cursor::new(Vec::new()), &object)
+        .map_err(|e| PipeWireError(format!("{:?}", e)))?;
+    Ok(cursor.into_inner())
+}
+
+fn run(
+    node: Option<String>,
+    analysis: AnalysisSettings,
+    sender: Sender<AudioAction>,
+    quit: pw::channel::Receiver<()>,
+    ready: &mpsc::Sender<Result<(), String>>,
+) -> Result<(), Box<dyn Error>> {
+    pw::init();
+
+    let mainloop = pw::MainLoop::new()?;
+    let context = pw::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+
+    let _quit = quit.attach(&mainloop, {
+        let mainloop = mainloop.clone();
+        move |_| mainloop.quit()
+    });
+
+    let mut props = properties! {
+        *pw::keys::MEDIA_TYPE => "Audio",
+        *pw::keys::MEDIA_CATEGORY => "Capture",
+        *pw::keys::MEDIA_ROLE => "Music",
+        *pw::keys::APP_NAME => "Chromatic",
+    };
+    if let Some(node) = node.as_ref() {
+        props.insert("target.object", node.as_str());
+    }
+
+    let stream = pw::stream::Stream::new(&core, "chromatic-capture", props)?;
+
+    let buffer_size = analysis.buffer_size as usize;
+    let mut analyser = Analyser::new(analysis, SAMPLE_RATE as f64, sender)?;
+    //pipewire delivers a quantum at a time, the analyser wants buffer_size samples
+    let mut pending: Vec<f32> = Vec::with_capacity(2 * buffer_size);
+
+    let _listener = stream
+        .add_local_listener_with_user_data(())
+        .process(move |stream, _| {
+            let mut buffer = match stream.dequeue_buffer() {
+                Some(buffer) => buffer,
+                None => return,
+            };
+
+            let datas = buffer.datas_mut();
+            if datas.is_empty() {
+                return;
+            }
+            let data = &mut datas[0];
+            let size = data.chunk().size() as usize;
+
+            if let Some(bytes) = data.data() {
+                pending.extend(
+                    bytes[..size.min(bytes.len())]
+                        .chunks_exact(4)
+                        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                );
+            }
+
+            while pending.len() >= buffer_size {
+                analyser.process(&pending[..buffer_size]);
+                pending.drain(..buffer_size);
+            }
+        })
+        .register()?;
+
+    let values = format_params()?;
+    let mut params = [Pod::from_bytes(&values).ok_or_else(|| PipeWireError("invalid format".into()))?];
+
+    stream.connect(
+        spa::Direction::Input,
+        None,
+        //no RT_PROCESS, the analyser runs on this thread's loop rather than the realtime thread
+        pw::stream::StreamFlags::AUTOCONNECT | pw::stream::StreamFlags::MAP_BUFFERS,
+        &mut params,
+    )?;
+
+    debug!("pipewire capture -> {:?}", node);
+    let _ = ready.send(Ok(()));
+
+    mainloop.run();
+    Ok(())
+}
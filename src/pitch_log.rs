@@ -0,0 +1,217 @@
+/* pitch_log.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//machine readable log of every analysed frame, as json lines or csv
+
+use std::{error::Error, fmt, fs::File, io, io::Write, path::Path};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::tuning::Note;
+
+const CSV_HEADER: &str = "timestamp,frequency,note,octave,cents,confidence";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Json,
+    Csv,
+}
+
+impl LogFormat {
+    pub fn from_name(name: &str) -> Option<LogFormat> {
+        match name.to_lowercase().as_str() {
+            "json" | "jsonl" => Some(LogFormat::Json),
+            "csv" => Some(LogFormat::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogFormat::Json => "json",
+            LogFormat::Csv => "csv",
+        }
+    }
+
+    //csv for .csv files, json lines for anything else
+    pub fn from_path(path: &Path) -> LogFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => LogFormat::Csv,
+            _ => LogFormat::Json,
+        }
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn csv_field(text: &str) -> String {
+    match text.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.to_string(),
+    }
+}
+
+//one line of the log, without the line break
+fn format_frame(format: LogFormat, timestamp: f64, frequency: f32, confidence: f32, note: Option<&Note>) -> String {
+    match format {
+        LogFormat::Json => {
+            let note_fields = match note {
+                Some(note) => format!(
+                    "\"note\":{},\"octave\":{},\"cents\":{:.2}",
+                    json_string(&note.name), note.octave, note.cents
+                ),
+                None => "\"note\":null,\"octave\":null,\"cents\":null".to_string(),
+            };
+            format!(
+                "{{\"timestamp\":{:.3},\"frequency\":{:.2},{},\"confidence\":{:.3}}}",
+                timestamp, frequency.max(0.0), note_fields, confidence
+            )
+        }
+        LogFormat::Csv => {
+            let note_fields = match note {
+                Some(note) => format!("{},{},{:.2}", csv_field(&note.name), note.octave, note.cents),
+                None => ",,".to_string(),
+            };
+            format!("{:.3},{:.2},{},{:.3}", timestamp, frequency.max(0.0), note_fields, confidence)
+        }
+    }
+}
+
+pub struct PitchLog {
+    writer: Box<dyn Write>,
+    format: LogFormat,
+}
+
+impl fmt::Debug for PitchLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PitchLog")
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+impl PitchLog {
+    //a path of "-" logs to stdout
+    pub fn create(path: &Path, format: LogFormat) -> Result<PitchLog, Box<dyn Error>> {
+        let writer: Box<dyn Write> = match path.to_str() {
+            Some("-") => Box::new(io::stdout()),
+            _ => Box::new(io::BufWriter::new(File::create(path)?)),
+        };
+
+        let mut log = PitchLog { writer, format };
+        if format == LogFormat::Csv {
+            writeln!(log.writer, "{}", CSV_HEADER)?;
+        }
+        Ok(log)
+    }
+
+    //note is None for frames where nothing was heard
+    pub fn write_frame(&mut self, frequency: f32, confidence: f32, note: Option<&Note>) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+
+        writeln!(self.writer, "{}", format_frame(self.format, timestamp, frequency, confidence, note))?;
+
+        //keep the log readable while it is being written, e.g. through a pipe
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(name: &str, octave: i32, cents: f64) -> Note {
+        Note {
+            name: name.to_string(),
+            octave,
+            frequency: 440.0,
+            cents,
+        }
+    }
+
+    #[test]
+    fn test_format_names() {
+        assert_eq!(LogFormat::from_name("JSON"), Some(LogFormat::Json));
+        assert_eq!(LogFormat::from_name("jsonl"), Some(LogFormat::Json));
+        assert_eq!(LogFormat::from_name("csv"), Some(LogFormat::Csv));
+        assert_eq!(LogFormat::from_name("xml"), None);
+
+        assert_eq!(LogFormat::from_path(Path::new("log.CSV")), LogFormat::Csv);
+        assert_eq!(LogFormat::from_path(Path::new("log.jsonl")), LogFormat::Json);
+        assert_eq!(LogFormat::from_path(Path::new("-")), LogFormat::Json);
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("A"), "\"A\"");
+        assert_eq!(json_string("C♯"), "\"C♯\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_string("\n\t"), "\"\\u000a\\u0009\"");
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("C♯"), "C♯");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_json_frame() {
+        let line = format_frame(LogFormat::Json, 1700000000.1234, 440.123, 0.98765, Some(&note("A", 4, 0.5)));
+        assert_eq!(
+            line,
+            "{\"timestamp\":1700000000.123,\"frequency\":440.12,\"note\":\"A\",\"octave\":4,\"cents\":0.50,\"confidence\":0.988}"
+        );
+
+        //silence, the detector reports negative frequencies as nothing
+        let line = format_frame(LogFormat::Json, 1.0, -1.0, 0.0, None);
+        assert_eq!(
+            line,
+            "{\"timestamp\":1.000,\"frequency\":0.00,\"note\":null,\"octave\":null,\"cents\":null,\"confidence\":0.000}"
+        );
+    }
+
+    #[test]
+    fn test_csv_frame() {
+        let line = format_frame(LogFormat::Csv, 2.5, 277.18, 0.9, Some(&note("C♯", 4, -12.345)));
+        assert_eq!(line, "2.500,277.18,C♯,4,-12.35,0.900");
+        assert_eq!(line.split(',').count(), CSV_HEADER.split(',').count());
+
+        let line = format_frame(LogFormat::Csv, 2.5, 0.0, 0.1, None);
+        assert_eq!(line, "2.500,0.00,,,,0.100");
+        assert_eq!(line.split(',').count(), CSV_HEADER.split(',').count());
+    }
+}
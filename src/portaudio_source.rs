@@ -0,0 +1,219 @@
+/* portaudio_source.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//devices are listed by pulse audio and captured through portaudio, pulse
+//descriptions are fuzzy matched against the portaudio device names
+
+use gtk::glib::Sender;
+
+use std::{error::Error, fmt, rc::Rc, thread};
+use std::sync::mpsc;
+use std::sync::mpsc::*;
+use log::{debug, error};
+
+use pulsectl::controllers::DeviceControl;
+use pulsectl::controllers::SourceController;
+
+use portaudio;
+
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+
+use super::i18n::i18n_k;
+use super::audio_source::{AudioSource, AudioSourceError, InputDevice};
+use super::recorder::{Analyser, AnalysisSettings};
+use super::window::AudioAction;
+
+pub struct PortAudioSource {
+    pa: Rc<portaudio::PortAudio>,
+    tx: Option<mpsc::Sender<()>>,
+}
+
+impl fmt::Debug for PortAudioSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PortAudioSource")
+            .field("running", &self.tx.is_some())
+            .finish()
+    }
+}
+
+impl PortAudioSource {
+    //pa is shared with the tone generator, only one instance is allowed
+    pub fn new(pa: Rc<portaudio::PortAudio>) -> PortAudioSource {
+        PortAudioSource { pa, tx: None }
+    }
+
+    fn running_mic_index(&self, device_name: &str) -> Result<portaudio::DeviceIndex, Box<dyn Error>> {
+        let mut ratio = 0;
+        let mut mic_index = self.pa.default_input_device()?;
+        let matcher = SkimMatcherV2::default();
+
+        for device in self.pa.devices()? {
+            let (idx, info) = device?;
+            let in_channels = info.max_input_channels;
+
+            if in_channels > 0 {
+
+                match matcher.fuzzy_match(device_name, &info.name) {
+                    Some(val) => {
+                        if val > ratio {
+                            ratio = val;
+                            mic_index = idx;
+                        }
+                    }
+                    None => (),
+                }
+            }
+        }
+
+        debug!("{:#?} {}", &mic_index, ratio);
+
+        Ok(mic_index)
+    }
+}
+
+impl AudioSource for PortAudioSource {
+    fn devices(&self) -> Result<Vec<InputDevice>, Box<dyn Error>> {
+        let mut array = Vec::new();
+        let mut handler = SourceController::create()?;
+        for device in handler.list_devices()? {
+            if device.monitor.is_none() {
+                if let Some(description) = device.description {
+                    array.push(InputDevice::named(&description));
+                }
+            }
+        }
+
+        Ok(array)
+    }
+
+    fn running_device(&self) -> Result<InputDevice, Box<dyn Error>> {
+        let mut handler = SourceController::create()?;
+        for device in handler.list_devices()? {
+            if device.state == pulsectl::controllers::types::DevState::Running {
+                debug!("Pulse Audio Source: {:?}", device.name);
+                if let Some(description) = device.description {
+                    return Ok(InputDevice::named(&description));
+                }
+            }
+        }
+        return Err(Box::new(AudioSourceError("No devices found".into())));
+    }
+
+    fn start(&mut self, device: Option<&InputDevice>, analysis: AnalysisSettings, glib_sender: Sender<AudioAction>) -> Result<(), Box<dyn Error>> {
+        // MANY THANKS TO https://dev.to/maniflames/audio-visualization-with-rust-4nhg
+
+        let mic_index = match device {
+            Some(device) => self.running_mic_index(&device.id)?,
+            None => self.pa.default_input_device()?,
+        };
+
+        debug!("portaudio -> start stream {:?}", mic_index);
+
+        let default_low_input_latency = self.pa.device_info(mic_index)?.default_low_input_latency;
+        let default_sample_rate = self.pa.device_info(mic_index)?.default_sample_rate;
+
+        // Set parameters for the stream settings.
+        // We pass which mic should be used, how many channels are used,
+        // whether all the values of all the channels should be passed in a
+        // single audiobuffer and the latency that should be considered
+        let input_params =
+            portaudio::StreamParameters::<f32>::new(mic_index, 1, true, default_low_input_latency);
+
+        // Settings for an inputstream.
+        // Here we pass the stream parameters we set before,
+        // the sample rate of the mic and the amount values we want to receive
+        let buffer_size = analysis.buffer_size;
+
+        debug!("buffer size {}", buffer_size);
+
+        let input_settings =
+            portaudio::InputStreamSettings::new(input_params, default_sample_rate, buffer_size);
+
+        // Creating a channel so we can receive audio values asynchronously
+        let (sender, receiver) = channel();
+
+        // Additional channel to kill thread when switching devices
+        let (tx, rx) = mpsc::channel::<()>();
+
+        //dropping the sender kills the previous stream thread
+        self.tx.replace(tx);
+
+        // A callback function that should be as short as possible so we send all the info to a different thread
+        let callback =
+            move | portaudio::InputStreamCallbackArgs { buffer, .. } | match sender.send(buffer) {
+                Ok(_) => portaudio::Continue,
+                Err(_) => portaudio::Complete,
+            };
+
+        // Creating & starting the input stream with our settings & callback
+        let mut stream = self
+            .pa
+            .open_non_blocking_stream(input_settings, callback)?;
+
+        stream.start()?;
+
+        //RECEIVE AUDIO BUFFER AND SEND TO GLIB LOOP
+        thread::spawn(move || {
+            //e.g. a detection method that cannot use the buffer size
+            let mut analyser = match Analyser::new(analysis, default_sample_rate, glib_sender.clone()) {
+                Ok(analyser) => analyser,
+                Err(e) => {
+                    error!("portaudio -> unable to create analyser: {}", e);
+                    let message = i18n_k("Unable to analyse audio ({error})", &[("error", &e.to_string())]);
+                    if let Err(e) = glib_sender.send(AudioAction::Error(message)) {
+                        error!("{}", e);
+                    }
+                    if let Err(e) = stream.close() {
+                        error!("portaudio -> unable to close stream: {}", e);
+                    }
+                    return;
+                }
+            };
+
+            debug!("portaudio -> stream thread");
+
+            while stream.is_active().unwrap() {
+                while let Ok(buffer) = receiver.try_recv() {
+                    analyser.process(buffer);
+                }
+
+                //kill stream thread if channel disconnect
+                match rx.try_recv() {
+                    Ok(_) | Err(mpsc::TryRecvError::Disconnected) => {
+                        error!("disconnected channel, stream should end");
+                        break;
+                    }
+                    Err(mpsc::TryRecvError::Empty) => {}
+                }
+
+            }
+
+            debug!("stream closing ...");
+            stream.close().unwrap();
+        });
+
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.tx = None; //dropping the sender ends the stream thread
+    }
+}
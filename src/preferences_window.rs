@@ -22,16 +22,20 @@ use adw::prelude::*;
 use adw::subclass::prelude::*;
 use gtk::{gio, gio::SettingsBindFlags, glib, glib::clone};
 
-use std::{cell::RefCell, error::Error};
+use std::{cell::{Cell, RefCell}, error::Error, path::{Path, PathBuf}};
 use log::{debug, error};
 
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
 
-use pulsectl::controllers::DeviceControl;
-use pulsectl::controllers::SourceController;
 
 use super::util;
+use super::recorder;
+use super::toasts;
+use super::i18n::{i18n, i18n_k};
+use super::scala::{KeyboardMapping, Scale};
+use super::tuning::TRANSPOSITIONS;
+use super::instruments::{self, InstrumentPreset};
 
 mod imp {
     use super::*;
@@ -48,12 +52,117 @@ mod imp {
         #[template_child(id = "switch_title_visible")]
         pub switch_title_visible: TemplateChild<gtk::Switch>,
 
+        #[template_child(id = "switch_cent_decimals")]
+        pub switch_cent_decimals: TemplateChild<gtk::Switch>,
+
+        #[template_child(id = "display_mode_row")]
+        pub display_mode_row: TemplateChild<adw::ComboRow>,
+
+        #[template_child(id = "switch_history_visible")]
+        pub switch_history_visible: TemplateChild<gtk::Switch>,
+
+        #[template_child(id = "history_axis_row")]
+        pub history_axis_row: TemplateChild<adw::ComboRow>,
+
+        #[template_child(id = "history_window_adj")]
+        pub history_window_adj: TemplateChild<gtk::Adjustment>,
+
+        #[template_child(id = "switch_osc")]
+        pub switch_osc: TemplateChild<gtk::Switch>,
+
+        #[template_child(id = "osc_host_row")]
+        pub osc_host_row: TemplateChild<adw::EntryRow>,
+
+        #[template_child(id = "osc_port_adj")]
+        pub osc_port_adj: TemplateChild<gtk::Adjustment>,
+
+        #[template_child(id = "backend_row")]
+        pub backend_row: TemplateChild<adw::ComboRow>,
+
+        #[template_child(id = "jack_passthrough_row")]
+        pub jack_passthrough_row: TemplateChild<adw::ActionRow>,
+
+        #[template_child(id = "switch_jack_passthrough")]
+        pub switch_jack_passthrough: TemplateChild<gtk::Switch>,
+
+        #[template_child(id = "synthetic_snr_row")]
+        pub synthetic_snr_row: TemplateChild<adw::ActionRow>,
+
+        #[template_child(id = "synthetic_snr_adj")]
+        pub synthetic_snr_adj: TemplateChild<gtk::Adjustment>,
+
         #[template_child(id = "device_row")]
         pub device_row: TemplateChild<adw::ComboRow>,
 
+        #[template_child(id = "device_level_bar")]
+        pub device_level_bar: TemplateChild<gtk::LevelBar>,
+
+        #[template_child(id = "tuning_system_row")]
+        pub tuning_system_row: TemplateChild<adw::ComboRow>,
+
+        #[template_child(id = "temperament_row")]
+        pub temperament_row: TemplateChild<adw::ComboRow>,
+
+        #[template_child(id = "tonic_row")]
+        pub tonic_row: TemplateChild<adw::ComboRow>,
+
+        #[template_child(id = "edo_row")]
+        pub edo_row: TemplateChild<adw::ActionRow>,
+
+        #[template_child(id = "edo_adj")]
+        pub edo_adj: TemplateChild<gtk::Adjustment>,
+
+        #[template_child(id = "scala_row")]
+        pub scala_row: TemplateChild<adw::ActionRow>,
+
+        #[template_child(id = "scala_button")]
+        pub scala_button: TemplateChild<gtk::Button>,
+
+        #[template_child(id = "kbm_row")]
+        pub kbm_row: TemplateChild<adw::ActionRow>,
+
+        #[template_child(id = "kbm_button")]
+        pub kbm_button: TemplateChild<gtk::Button>,
+
+        #[template_child(id = "kbm_clear_button")]
+        pub kbm_clear_button: TemplateChild<gtk::Button>,
+
+        #[template_child(id = "transposition_row")]
+        pub transposition_row: TemplateChild<adw::ComboRow>,
+
+        #[template_child(id = "transposition_adj")]
+        pub transposition_adj: TemplateChild<gtk::Adjustment>,
+
+        #[template_child(id = "instrument_row")]
+        pub instrument_row: TemplateChild<adw::ComboRow>,
+
+        #[template_child(id = "remove_preset_button")]
+        pub remove_preset_button: TemplateChild<gtk::Button>,
+
+        #[template_child(id = "preset_name_row")]
+        pub preset_name_row: TemplateChild<adw::EntryRow>,
+
+        #[template_child(id = "preset_strings_row")]
+        pub preset_strings_row: TemplateChild<adw::EntryRow>,
+
+        #[template_child(id = "add_preset_button")]
+        pub add_preset_button: TemplateChild<gtk::Button>,
+
+        #[template_child(id = "algorithm_row")]
+        pub algorithm_row: TemplateChild<adw::ComboRow>,
+
         #[template_child(id = "buffer_adj")]
         pub buffer_adj: TemplateChild<gtk::Adjustment>,
 
+        #[template_child(id = "silence_adj")]
+        pub silence_adj: TemplateChild<gtk::Adjustment>,
+
+        #[template_child(id = "silence_spin")]
+        pub silence_spin: TemplateChild<gtk::SpinButton>,
+
+        #[template_child(id = "confidence_adj")]
+        pub confidence_adj: TemplateChild<gtk::Adjustment>,
+
         #[template_child(id = "gauge_hang_adj")]
         pub gauge_hang_adj: TemplateChild<gtk::Adjustment>,
 
@@ -63,12 +172,20 @@ mod imp {
         #[template_child(id = "gauge_rest_adj")]
         pub gauge_rest_adj: TemplateChild<gtk::Adjustment>,
 
+        #[template_child(id = "reference_pitch_adj")]
+        pub reference_pitch_adj: TemplateChild<gtk::Adjustment>,
+
         #[template_child(id = "buffer_spin")]
         pub buffer_spin: TemplateChild<gtk::SpinButton>,
 
         pub settings: gio::Settings,
         pub devices_model: gtk::StringList,
         pub selected_device: RefCell<String>,
+        pub file_chooser: RefCell<Option<gtk::FileChooserNative>>,
+        pub instruments_model: gtk::StringList,
+        pub presets: RefCell<Vec<InstrumentPreset>>,
+        pub loading_presets: Cell<bool>,
+        pub level_handler: RefCell<Option<glib::SignalHandlerId>>,
     }
 
     #[glib::object_subclass]
@@ -90,15 +207,56 @@ mod imp {
                 switch_device_select: TemplateChild::default(),
                 switch_gauge_visible: TemplateChild::default(),
                 switch_title_visible: TemplateChild::default(),
+                switch_cent_decimals: TemplateChild::default(),
+                display_mode_row: TemplateChild::default(),
+                switch_history_visible: TemplateChild::default(),
+                history_axis_row: TemplateChild::default(),
+                history_window_adj: TemplateChild::default(),
+                switch_osc: TemplateChild::default(),
+                osc_host_row: TemplateChild::default(),
+                osc_port_adj: TemplateChild::default(),
+                backend_row: TemplateChild::default(),
+                jack_passthrough_row: TemplateChild::default(),
+                switch_jack_passthrough: TemplateChild::default(),
+                synthetic_snr_row: TemplateChild::default(),
+                synthetic_snr_adj: TemplateChild::default(),
                 device_row: TemplateChild::default(),
+                device_level_bar: TemplateChild::default(),
+                algorithm_row: TemplateChild::default(),
+                tuning_system_row: TemplateChild::default(),
+                temperament_row: TemplateChild::default(),
+                tonic_row: TemplateChild::default(),
+                edo_row: TemplateChild::default(),
+                edo_adj: TemplateChild::default(),
+                scala_row: TemplateChild::default(),
+                scala_button: TemplateChild::default(),
+                kbm_row: TemplateChild::default(),
+                kbm_button: TemplateChild::default(),
+                kbm_clear_button: TemplateChild::default(),
+                transposition_row: TemplateChild::default(),
+                transposition_adj: TemplateChild::default(),
+                instrument_row: TemplateChild::default(),
+                remove_preset_button: TemplateChild::default(),
+                preset_name_row: TemplateChild::default(),
+                preset_strings_row: TemplateChild::default(),
+                add_preset_button: TemplateChild::default(),
                 buffer_adj: TemplateChild::default(),
+                silence_adj: TemplateChild::default(),
+                silence_spin: TemplateChild::default(),
+                confidence_adj: TemplateChild::default(),
                 gauge_hang_adj: TemplateChild::default(),
                 label_hang_adj: TemplateChild::default(),
                 gauge_rest_adj: TemplateChild::default(),
+                reference_pitch_adj: TemplateChild::default(),
                 buffer_spin: TemplateChild::default(),
                 settings: util::settings_manager(),
                 devices_model: gtk::StringList::new(&[]),
                 selected_device: RefCell::new("".to_string()),
+                file_chooser: RefCell::new(None),
+                instruments_model: gtk::StringList::new(&[]),
+                presets: RefCell::new(Vec::new()),
+                loading_presets: Cell::new(false),
+                level_handler: RefCell::new(None),
             }
         }
     }
@@ -146,6 +304,76 @@ impl PreferencesWindow {
             imp.devices_model.append(&d);
         }
 
+        //only the backends built in are listed, the row is hidden if there is no choice
+        let backends = recorder::available_backends();
+        let names: Vec<&str> = backends.iter().map(|(_, name)| name.as_str()).collect();
+        imp.backend_row.set_model(Some(&gtk::StringList::new(&names)));
+        imp.backend_row.set_visible(backends.len() > 1);
+
+        let backend = imp.settings.enum_("audio-backend");
+        let position = backends.iter().position(|(value, _)| *value == backend).unwrap_or(0);
+        imp.backend_row.set_selected(position as u32);
+        self.update_backend_rows();
+
+        imp.backend_row.connect_selected_notify(
+            clone!(@weak self as this => @default-panic, move |_value| {
+                let imp = this.imp();
+                let (value, _) = recorder::available_backends()[imp.backend_row.selected() as usize].clone();
+                if let Err(e) = imp.settings.set_enum("audio-backend", value) {
+                    error!("unable to set audio backend: {}", e);
+                }
+            }),
+        );
+
+        imp.settings
+            .bind("jack-passthrough", &*imp.switch_jack_passthrough, "active")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
+
+        imp.settings
+            .bind("synthetic-snr", &*imp.synthetic_snr_adj, "value")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
+
+            //applied to the running test signal, restarting it on every step
+            //of the slider would interrupt it
+            imp.settings.connect_changed(
+                Some("synthetic-snr"),
+                |settings, _name| {
+                    util::recorder().set_synthetic_snr(settings.double("synthetic-snr"));
+                },
+            );
+
+            imp.settings.connect_changed(
+                Some("jack-passthrough"),
+                clone!(@strong self as this => move |_settings, _name| {
+                    if this.imp().settings.enum_("audio-backend") != 2 {
+                        return;
+                    }
+                    if let Err(e) = util::recorder().switch_backend() {
+                        error!("unable to restart jack client: {}", e);
+                    }
+                }),
+            );
+
+            imp.settings.connect_changed(
+                Some("audio-backend"),
+                clone!(@strong self as this => move |_settings, _name| {
+                    let imp = this.imp();
+                    imp.backend_row.set_sensitive(false);
+                    match util::recorder().switch_backend() {
+                        Ok(_) => debug!("switched backends"),
+                        Err(e) => {
+                            error!("unable to switch backends: {}", e);
+                            toasts::add_error_toast(i18n("Unable to initialize audio backend."));
+                        },
+                    }
+                    this.refresh_devices();
+                    this.update_backend_rows();
+                    imp.backend_row.set_sensitive(true);
+                }),
+            );
+
         imp.settings
             .bind("choose-device", &*imp.switch_device_select, "active")
             .flags(SettingsBindFlags::DEFAULT)
@@ -166,59 +394,108 @@ impl PreferencesWindow {
             .flags(SettingsBindFlags::DEFAULT)
             .build();
 
-        imp.settings.connect_changed(
-            Some("selected-device"),
-            clone!(@strong self as this => move |_settings, _name| {
-                debug!("Pref window -> settings update selected device");
+        imp.settings
+            .bind("show-cent-decimals", &*imp.switch_cent_decimals, "active")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
+
+        imp.display_mode_row.set_selected(imp.settings.enum_("display-mode") as u32);
+
+        imp.display_mode_row.connect_selected_notify(
+            clone!(@weak self as this => @default-panic, move |_value| {
                 let imp = this.imp();
-                let device_name = imp.settings.string("selected-device").to_string();
+                if let Err(e) = imp.settings.set_enum("display-mode", imp.display_mode_row.selected() as i32) {
+                    error!("unable to set display mode: {}", e);
+                }
+            }),
+        );
 
+        imp.settings
+            .bind("show-history", &*imp.switch_history_visible, "active")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
 
-                let selected = imp.device_row.selected();
-                let device_name_row = imp.devices_model.string(selected).unwrap().to_string();
+        imp.settings
+            .bind("history-window", &*imp.history_window_adj, "value")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
 
-                if device_name != device_name_row {
-                    match util::recorder().switch_stream(Some(device_name.clone())) {
-                        Ok(_) => {
-                            debug!("switched streams");
-                            imp.device_row.set_subtitle(&device_name);
-                            this.set_device_selected(device_name);
-                        },
-                        Err(e) => debug!("{}", e),
-                    }
-                } else {
-                    debug!("already set from row");
-                }
+        imp.settings
+            .bind("osc-enabled", &*imp.switch_osc, "active")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
 
+        imp.settings
+            .bind("osc-port", &*imp.osc_port_adj, "value")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
 
+        //only save the host once it is applied, not on every keystroke
+        imp.osc_host_row.set_text(&imp.settings.string("osc-host"));
+        imp.osc_host_row.connect_apply(
+            clone!(@weak self as this => move |row| {
+                let imp = this.imp();
+                if let Err(e) = imp.settings.set_string("osc-host", row.text().trim()) {
+                    error!("unable to set osc host: {}", e);
+                }
             }),
         );
 
+        imp.history_axis_row.set_selected(imp.settings.enum_("history-axis") as u32);
 
+        imp.history_axis_row.connect_selected_notify(
+            clone!(@weak self as this => @default-panic, move |_value| {
+                let imp = this.imp();
+                if let Err(e) = imp.settings.set_enum("history-axis", imp.history_axis_row.selected() as i32) {
+                    error!("unable to set history axis: {}", e);
+                }
+            }),
+        );
+
+        //the window restarts the stream, only the row follows here
         imp.settings.connect_changed(
-            Some("choose-device"),
+            Some("selected-device"),
             clone!(@strong self as this => move |_settings, _name| {
-                debug!("Pref window -> settings choose-device");
+                debug!("Pref window -> settings update selected device");
                 let imp = this.imp();
-                let manual = imp.settings.boolean("choose-device");
                 let device_name = imp.settings.string("selected-device").to_string();
 
 
+                //the model is empty when the backend has no devices to list
+                let selected = imp.device_row.selected();
+                let device_name_row = imp.devices_model.string(selected).map(|s| s.to_string());
 
-                if manual {
-                    match util::recorder().switch_stream(None) {
-                        Ok(_) => debug!("switched streams"),
-                        Err(e) => debug!("{}", e),
-                    }
+                if device_name_row.as_deref() != Some(device_name.as_str()) {
+                    imp.device_row.set_subtitle(&device_name);
+                    this.set_device_selected(device_name);
                 } else {
-                    match util::recorder().switch_stream(Some(device_name)) {
-                        Ok(_) => debug!("switched streams"),
-                        Err(e) => debug!("{}", e),
-                    }
+                    debug!("already set from row");
                 }
 
+
+            }),
+        );
+
+        //show the level of the running stream next to the device
+        let recorder = util::recorder();
+        let handler = recorder.connect_local(
+            "level",
+            false,
+            clone!(@weak self as this => @default-return None, move |value| {
+                if let Some(Ok(rms)) = value.get(1).map(|v| v.get::<f32>()) {
+                    this.imp().device_level_bar.set_value(util::level_fraction(rms));
+                }
+                None
             }),
         );
+        imp.level_handler.replace(Some(handler));
+
+        self.connect_close_request(move |this| {
+            if let Some(handler) = this.imp().level_handler.take() {
+                recorder.disconnect(handler);
+            }
+            gtk::Inhibit(false)
+        });
 
         //SET THE DEVICE ROW CURRENT UI FROM SETTINGS
         let device_name = imp.settings.string("selected-device").to_string();
@@ -230,7 +507,13 @@ impl PreferencesWindow {
                 debug!("Pref window -> device row select notify");
                 let imp = this.imp();
                 let selected = imp.device_row.selected();
-                let device_name = imp.devices_model.string(selected).unwrap().to_string();
+                let device_name = match imp.devices_model.string(selected) {
+                    Some(name) => name.to_string(),
+                    None => {
+                        debug!("no device selected");
+                        return;
+                    }
+                };
                 imp.device_row.set_subtitle(device_name.as_str()); // set the subtitle of the AdwComboRow
 
                 match util::recorder().switch_stream(Some(device_name)) {
@@ -264,6 +547,63 @@ impl PreferencesWindow {
                 }),
             );
 
+        imp.algorithm_row.set_selected(imp.settings.enum_("pitch-algorithm") as u32);
+
+        imp.algorithm_row.connect_selected_notify(
+            clone!(@weak self as this => @default-panic, move |_value| {
+                let imp = this.imp();
+                if let Err(e) = imp.settings.set_enum("pitch-algorithm", imp.algorithm_row.selected() as i32) {
+                    error!("unable to set pitch algorithm: {}", e);
+                }
+            }),
+        );
+
+            //restart the stream so the new detector is used
+            imp.settings.connect_changed(
+                Some("pitch-algorithm"),
+                clone!(@strong self as this => move |_settings, _name| {
+                    let imp = this.imp();
+                    let device_name = imp.settings.string("selected-device").to_string();
+                    imp.algorithm_row.set_sensitive(false);
+                    match util::recorder().switch_stream(Some(device_name)) {
+                        Ok(_) => debug!("switched streams"),
+                        Err(e) => debug!("{}", e),
+                    }
+                    imp.algorithm_row.set_sensitive(true);
+                }),
+            );
+
+        imp.settings
+            .bind("silence-threshold", &*imp.silence_adj, "value")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
+
+            imp.settings.connect_changed(
+                Some("silence-threshold"),
+                clone!(@strong self as this => move |_settings, _name| {
+                    let imp = this.imp();
+                    let device_name = imp.settings.string("selected-device").to_string();
+                    imp.silence_spin.set_sensitive(false);
+                    match util::recorder().switch_stream(Some(device_name)) {
+                        Ok(_) => debug!("switched streams"),
+                        Err(e) => debug!("{}", e),
+                    }
+                    imp.silence_spin.set_sensitive(true);
+                }),
+            );
+
+        imp.settings
+            .bind("minimum-confidence", &*imp.confidence_adj, "value")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
+
+            imp.settings.connect_changed(
+                Some("minimum-confidence"),
+                clone!(@strong self as this => move |_settings, _name| {
+                    util::window().update_settings();
+                }),
+            );
+
         imp.settings
             .bind("gauge-hang", &*imp.gauge_hang_adj, "value")
             .flags(SettingsBindFlags::DEFAULT)
@@ -300,10 +640,408 @@ impl PreferencesWindow {
                 }),
             );
 
+        imp.settings
+            .bind("reference-pitch", &*imp.reference_pitch_adj, "value")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
+
+            imp.settings.connect_changed(
+                Some("reference-pitch"),
+                clone!(@strong self as this => move |_settings, _name| {    
+                    util::window().update_settings();
+                }),
+            );
+
+        //tuning system and temperament are enum keys, so keep the combo rows in sync by hand
+        imp.tuning_system_row.set_selected(imp.settings.enum_("tuning-system") as u32);
+        imp.temperament_row.set_selected(imp.settings.enum_("temperament") as u32);
+        imp.tonic_row.set_selected(imp.settings.int("temperament-tonic") as u32);
+        self.update_tuning_rows();
+
+        imp.tuning_system_row.connect_selected_notify(
+            clone!(@weak self as this => @default-panic, move |_value| {
+                let imp = this.imp();
+                match imp.settings.set_enum("tuning-system", imp.tuning_system_row.selected() as i32) {
+                    Ok(_) => util::window().update_settings(),
+                    Err(e) => error!("unable to set tuning system: {}", e),
+                }
+                this.update_tuning_rows();
+            }),
+        );
+
+        imp.temperament_row.connect_selected_notify(
+            clone!(@weak self as this => @default-panic, move |_value| {
+                let imp = this.imp();
+                match imp.settings.set_enum("temperament", imp.temperament_row.selected() as i32) {
+                    Ok(_) => util::window().update_settings(),
+                    Err(e) => error!("unable to set temperament: {}", e),
+                }
+                this.update_tuning_rows();
+            }),
+        );
+
+        imp.tonic_row.connect_selected_notify(
+            clone!(@weak self as this => @default-panic, move |_value| {
+                let imp = this.imp();
+                match imp.settings.set_int("temperament-tonic", imp.tonic_row.selected() as i32) {
+                    Ok(_) => util::window().update_settings(),
+                    Err(e) => error!("unable to set tonic: {}", e),
+                }
+            }),
+        );
+
+        imp.settings
+            .bind("edo-divisions", &*imp.edo_adj, "value")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
+
+            imp.settings.connect_changed(
+                Some("edo-divisions"),
+                clone!(@strong self as this => move |_settings, _name| {
+                    util::window().update_settings();
+                }),
+            );
+
+        imp.settings
+            .bind("transposition", &*imp.transposition_adj, "value")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
+
+        self.update_transposition_row();
+
+            imp.settings.connect_changed(
+                Some("transposition"),
+                clone!(@strong self as this => move |_settings, _name| {
+                    this.update_transposition_row();
+                    util::window().update_settings();
+                }),
+            );
+
+        //presets set the semitones, custom leaves them to the spin button
+        imp.transposition_row.connect_selected_notify(
+            clone!(@weak self as this => @default-panic, move |_value| {
+                let imp = this.imp();
+                if let Some(semitones) = TRANSPOSITIONS.get(imp.transposition_row.selected() as usize) {
+                    if let Err(e) = imp.settings.set_int("transposition", *semitones) {
+                        error!("unable to set transposition: {}", e);
+                    }
+                }
+            }),
+        );
+
+        imp.instrument_row.set_model(Some(&imp.instruments_model));
+        self.load_instrument_presets();
+
+        imp.instrument_row.connect_selected_notify(
+            clone!(@weak self as this => @default-panic, move |_value| {
+                let imp = this.imp();
+                if imp.loading_presets.get() {
+                    return;
+                }
+
+                //the first row is plain chromatic tuning
+                let id = match imp.instrument_row.selected() {
+                    0 => String::new(),
+                    i => imp.presets.borrow().get(i as usize - 1).map(|p| p.id.clone()).unwrap_or_default(),
+                };
+
+                if let Err(e) = imp.settings.set_string("instrument-preset", &id) {
+                    error!("unable to set instrument preset: {}", e);
+                }
+                this.update_instrument_rows();
+            }),
+        );
+
+            imp.settings.connect_changed(
+                Some("instrument-preset"),
+                clone!(@strong self as this => move |_settings, _name| {
+                    util::window().update_settings();
+                }),
+            );
+
+        imp.add_preset_button.connect_clicked(
+            clone!(@weak self as this => move |_button| {
+                this.add_custom_preset();
+            }),
+        );
+
+        imp.preset_strings_row.connect_entry_activated(
+            clone!(@weak self as this => move |_row| {
+                this.add_custom_preset();
+            }),
+        );
+
+        imp.remove_preset_button.connect_clicked(
+            clone!(@weak self as this => move |_button| {
+                this.remove_custom_preset();
+            }),
+        );
+
+        imp.scala_button.connect_clicked(
+            clone!(@weak self as this => move |_button| {
+                this.choose_scala_file("scala-file");
+            }),
+        );
+
+        imp.kbm_button.connect_clicked(
+            clone!(@weak self as this => move |_button| {
+                this.choose_scala_file("kbm-file");
+            }),
+        );
+
+        imp.kbm_clear_button.connect_clicked(
+            clone!(@weak self as this => move |_button| {
+                let imp = this.imp();
+                match imp.settings.set_string("kbm-file", "") {
+                    Ok(_) => util::window().update_settings(),
+                    Err(e) => error!("unable to clear keyboard mapping: {}", e),
+                }
+                this.update_tuning_rows();
+            }),
+        );
+
         
         Ok(())
     }
 
+    //fill the instrument row with chromatic, the built in and the custom presets
+    fn load_instrument_presets(&self) {
+        let imp = self.imp();
+        let presets = instruments::all_presets(&imp.settings);
+        let id = imp.settings.string("instrument-preset").to_string();
+        let selected = match presets.iter().position(|p| p.id == id) {
+            Some(index) => index + 1,
+            None => 0,
+        };
+
+        let mut names = vec![i18n("Chromatic")];
+        names.extend(presets.iter().map(|p| p.name.clone()));
+        let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+
+        imp.loading_presets.set(true);
+        imp.instruments_model.splice(0, imp.instruments_model.n_items(), &names);
+        imp.instrument_row.set_selected(selected as u32);
+        imp.loading_presets.set(false);
+
+        imp.presets.replace(presets);
+        self.update_instrument_rows();
+    }
+
+    fn update_instrument_rows(&self) {
+        let imp = self.imp();
+        let presets = imp.presets.borrow();
+        let preset = match imp.instrument_row.selected() {
+            0 => None,
+            i => presets.get(i as usize - 1),
+        };
+
+        match preset {
+            Some(preset) => imp.instrument_row.set_subtitle(&preset.string_names().join(" ")),
+            None => imp.instrument_row.set_subtitle(&i18n("Nearest note of the chromatic scale")),
+        }
+
+        imp.remove_preset_button.set_sensitive(preset.map(|p| p.custom).unwrap_or(false));
+    }
+
+    fn add_custom_preset(&self) {
+        let imp = self.imp();
+        let name = imp.preset_name_row.text().trim().to_string();
+        let notes = imp.preset_strings_row.text().to_string();
+
+        if name.is_empty() {
+            toasts::add_error_toast(i18n("Custom presets need a name"));
+            return;
+        }
+
+        let mut strings = Vec::new();
+        for note in notes.split(|c: char| c.is_whitespace() || c == ',').filter(|n| !n.is_empty()) {
+            match instruments::parse_note(note) {
+                Some(midi) => strings.push(midi),
+                None => {
+                    toasts::add_error_toast(i18n_k("Invalid note ({note})", &[("note", note)]));
+                    return;
+                }
+            }
+        }
+
+        if strings.is_empty() {
+            toasts::add_error_toast(i18n("Custom presets need at least one string"));
+            return;
+        }
+
+        //a preset with the same name is replaced
+        let id = format!("custom:{}", name);
+        let mut presets = imp.presets.borrow().clone();
+        presets.retain(|p| p.id != id);
+        presets.push(InstrumentPreset { id: id.clone(), name, strings, custom: true });
+
+        match instruments::save_custom_presets(&imp.settings, &presets) {
+            Ok(_) => {
+                imp.preset_name_row.set_text("");
+                imp.preset_strings_row.set_text("");
+                if let Err(e) = imp.settings.set_string("instrument-preset", &id) {
+                    error!("unable to set instrument preset: {}", e);
+                }
+                self.load_instrument_presets();
+            }
+            Err(e) => error!("unable to save custom presets: {}", e),
+        }
+    }
+
+    fn remove_custom_preset(&self) {
+        let imp = self.imp();
+        let id = imp.settings.string("instrument-preset").to_string();
+
+        let mut presets = imp.presets.borrow().clone();
+        presets.retain(|p| p.id != id || !p.custom);
+
+        match instruments::save_custom_presets(&imp.settings, &presets) {
+            Ok(_) => {
+                if let Err(e) = imp.settings.set_string("instrument-preset", "") {
+                    error!("unable to set instrument preset: {}", e);
+                }
+                self.load_instrument_presets();
+            }
+            Err(e) => error!("unable to save custom presets: {}", e),
+        }
+    }
+
+    //select the preset matching the transposition, or custom
+    fn update_transposition_row(&self) {
+        let imp = self.imp();
+        let semitones = imp.settings.int("transposition");
+
+        let index = match TRANSPOSITIONS.iter().position(|s| *s == semitones) {
+            Some(index) => index,
+            None => TRANSPOSITIONS.len(),
+        };
+
+        imp.transposition_row.set_selected(index as u32);
+    }
+
+    fn update_tuning_rows(&self) {
+        let imp = self.imp();
+        let tuning_system = imp.tuning_system_row.selected();
+
+        imp.temperament_row.set_sensitive(tuning_system == 0);
+        imp.tonic_row.set_sensitive(tuning_system == 0 && imp.temperament_row.selected() != 0);
+        imp.scala_row.set_sensitive(tuning_system == 1);
+        imp.kbm_row.set_sensitive(tuning_system == 1);
+        imp.edo_row.set_sensitive(tuning_system == 2);
+
+        let file_name = |key: &str| {
+            let path = imp.settings.string(key).to_string();
+            match Path::new(&path).file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => i18n("None"),
+            }
+        };
+
+        imp.scala_row.set_subtitle(&file_name("scala-file"));
+        imp.kbm_row.set_subtitle(&file_name("kbm-file"));
+        imp.kbm_clear_button.set_sensitive(!imp.settings.string("kbm-file").is_empty());
+    }
+
+    //pick a .scl or .kbm file for the settings key
+    fn choose_scala_file(&self, key: &'static str) {
+        let imp = self.imp();
+
+        let (title, filter_name, pattern) = match key {
+            "scala-file" => (i18n("Open Scale File"), i18n("Scala Scale Files"), "*.scl"),
+            _ => (i18n("Open Keyboard Mapping File"), i18n("Scala Keyboard Mapping Files"), "*.kbm"),
+        };
+
+        let dialog = gtk::FileChooserNative::new(
+            Some(&title),
+            Some(self),
+            gtk::FileChooserAction::Open,
+            Some(&i18n("_Open")),
+            Some(&i18n("_Cancel")),
+        );
+
+        let filter = gtk::FileFilter::new();
+        filter.set_name(Some(&filter_name));
+        filter.add_pattern(pattern);
+        dialog.add_filter(&filter);
+
+        dialog.connect_response(
+            clone!(@weak self as this => move |dialog, response| {
+                if response == gtk::ResponseType::Accept {
+                    match dialog.file().and_then(|file| file.path()) {
+                        Some(path) => this.load_scala_file(key, path),
+                        None => error!("unable to get path of chosen file"),
+                    }
+                }
+                this.imp().file_chooser.replace(None);
+            }),
+        );
+
+        dialog.show();
+
+        //native dialogs have to be kept alive until they respond
+        imp.file_chooser.replace(Some(dialog));
+    }
+
+    fn load_scala_file(&self, key: &str, path: PathBuf) {
+        let imp = self.imp();
+
+        //parse once here so malformed files are reported before they are saved
+        let result = match key {
+            "scala-file" => Scale::load(&path).map(|_| ()),
+            _ => KeyboardMapping::load(&path).map(|_| ()),
+        };
+
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+        match result {
+            Ok(_) => {
+                match imp.settings.set_string(key, &path.to_string_lossy()) {
+                    Ok(_) => util::window().update_settings(),
+                    Err(e) => error!("unable to set {}: {}", key, e),
+                }
+                self.update_tuning_rows();
+            }
+            Err(e) => {
+                error!("unable to load {}: {}", file_name, e);
+                toasts::add_error_toast(i18n_k("Unable to load {file_name} ({error})", &[("file_name", &file_name), ("error", &e.to_string())]));
+            }
+        }
+    }
+
+    //jack connects to ports and runs at the server's period, so the rows differ
+    fn update_backend_rows(&self) {
+        let imp = self.imp();
+        let jack = imp.settings.enum_("audio-backend") == 2 && cfg!(feature = "jack");
+
+        imp.jack_passthrough_row.set_visible(jack);
+        imp.synthetic_snr_row.set_visible(imp.settings.enum_("audio-backend") == 3);
+        imp.buffer_spin.set_sensitive(!jack);
+        imp.device_row.set_title(&match jack {
+            true => i18n("Connected Port"),
+            false => i18n("Device"),
+        });
+    }
+
+    //list the devices of the current backend
+    fn refresh_devices(&self) {
+        let imp = self.imp();
+
+        let devices = match self.input_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                error!("unable to list input devices: {}", e);
+                Vec::new()
+            }
+        };
+        let devices: Vec<&str> = devices.iter().map(|d| d.as_str()).collect();
+
+        imp.devices_model.splice(0, imp.devices_model.n_items(), &devices);
+
+        let device_name = imp.settings.string("selected-device").to_string();
+        imp.device_row.set_subtitle(&device_name);
+        self.set_device_selected(device_name);
+    }
+
     fn set_device_selected(&self, selected_name: String) {
         let imp = self.imp();
         let mut ratio = 0;
@@ -331,14 +1069,7 @@ impl PreferencesWindow {
 
     //get input devices from pulseaudio
     pub fn input_devices(&self) -> Result<Vec<String>, Box<dyn Error>> {
-        let mut array = Vec::new();
-        let mut handler = SourceController::create().unwrap();
-        for device in handler.list_devices()? {
-            if device.monitor.is_none() {
-                array.push(device.description.unwrap());
-            }           
-        }
-
-        Ok(array)
+        let devices = util::recorder().devices()?;
+        Ok(devices.into_iter().map(|device| device.name).collect())
     }
 }
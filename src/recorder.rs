@@ -22,38 +22,180 @@ use adw::prelude::*;
 use adw::subclass::prelude::*;
 use gtk::{gio, glib, glib::Sender};
 
-use std::{cell::Cell, cell::RefCell, error::Error, rc::Rc, fmt, thread};
-use std::sync::mpsc;
-use std::sync::mpsc::*;
+use std::{cell::Cell, cell::RefCell, error::Error, rc::Rc};
 use log::{debug, error};
 
-use pulsectl::controllers::DeviceControl;
-use pulsectl::controllers::SourceController;
-
 use portaudio;
 
 use aubio::{Pitch, PitchMode};
 
-use fuzzy_matcher::skim::SkimMatcherV2;
-use fuzzy_matcher::FuzzyMatcher;
-
 use super::window::AudioAction;
+use super::audio_source::{AudioSource, AudioSourceError, InputDevice};
+use super::portaudio_source::PortAudioSource;
+use super::synthetic_source::{Snr, SyntheticSource};
+#[cfg(feature = "pipewire")]
+use super::pipewire_source::PipeWireSource;
+#[cfg(feature = "jack")]
+use super::jack_source::JackSource;
 use super::util;
 use super::toasts;
-use super::i18n::i18n_k;
+use super::i18n::{i18n, i18n_k};
+
 
 
+//raw buffers are only used for display, the spectrum goes up to 8 kHz so they
+//are decimated to no less than twice that, and the oscilloscope needs no more
+//than a few thousand samples
+const RAW_MIN_RATE: f64 = 16000.0;
+const RAW_MAX_SAMPLES: usize = 4096;
+
+//keep every factor-th sample, averaged with the ones skipped so less of what
+//is above the new nyquist frequency folds back
+fn decimate(buffer: &[f32], factor: usize) -> Vec<f32> {
+    buffer
+        .chunks_exact(factor)
+        .map(|chunk| chunk.iter().sum::<f32>() / factor as f32)
+        .collect()
+}
 
-#[derive(Debug)]
-struct RecorderError(String);
+//rms and peak of a buffer in dBFS
+fn buffer_level(buffer: &[f32]) -> (f32, f32) {
+    if buffer.is_empty() {
+        return (-f32::INFINITY, -f32::INFINITY);
+    }
+
+    let rms = (buffer.iter().map(|s| s * s).sum::<f32>() / buffer.len() as f32).sqrt();
+    let peak = buffer.iter().fold(0.0_f32, |peak, s| peak.max(s.abs()));
+
+    (20.0 * rms.log10(), 20.0 * peak.log10())
+}
 
-impl fmt::Display for RecorderError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "There is an error: {}", self.0)
+//matches the values of the pitch-algorithm enum in the gschema
+fn pitch_mode(index: i32) -> PitchMode {
+    match index {
+        1 => PitchMode::Yinfft,
+        2 => PitchMode::Yinfast,
+        3 => PitchMode::Mcomb,
+        4 => PitchMode::Fcomb,
+        5 => PitchMode::Schmitt,
+        6 => PitchMode::Specacf,
+        _ => PitchMode::Yin,
     }
 }
 
-impl Error for RecorderError {}
+//analysis parameters read from the settings, in a form that can be sent to a thread
+#[derive(Clone, Copy, Debug)]
+pub struct AnalysisSettings {
+    pub algorithm: i32,
+    pub silence: f32,
+    pub buffer_size: u32,
+}
+
+impl AnalysisSettings {
+    pub fn load(settings: &gio::Settings) -> AnalysisSettings {
+        let buffer_size_setting: u32 = settings.double("buffer-size") as u32;
+
+        AnalysisSettings {
+            algorithm: settings.enum_("pitch-algorithm"),
+            silence: settings.double("silence-threshold") as f32,
+            buffer_size: 1024 * (buffer_size_setting / 1024),
+        }
+    }
+}
+
+//the analysis every audio source runs on its buffers: the level, raw buffers
+//for the displays and the pitch, all sent to the window
+pub struct Analyser {
+    detector: Pitch,
+    sender: Sender<AudioAction>,
+    sample_rate: f64,
+    raw_interval: usize,
+    raw_factor: usize,
+    buffer_count: usize,
+}
+
+impl Analyser {
+    //buffer_size samples are expected per call to process
+    pub fn new(analysis: AnalysisSettings, sample_rate: f64, sender: Sender<AudioAction>) -> Result<Analyser, Box<dyn Error>> {
+        let buffer_size = analysis.buffer_size;
+        debug!("pitch algorithm {}", analysis.algorithm);
+
+        let mut detector = Pitch::new(
+            pitch_mode(analysis.algorithm),
+            buffer_size as usize,
+            buffer_size as usize / 2,
+            sample_rate as u32,
+        )?;
+        detector.set_silence(analysis.silence);
+
+        Ok(Analyser {
+            detector,
+            sender,
+            sample_rate,
+            //raw buffers are only used for display, around 20 per second is plenty
+            raw_interval: ((0.05 * sample_rate / buffer_size as f64).ceil() as usize).max(1),
+            raw_factor: ((sample_rate / RAW_MIN_RATE).floor() as usize).max(1),
+            buffer_count: 0,
+        })
+    }
+
+    pub fn process(&mut self, buffer: &[f32]) {
+        let (rms, peak) = buffer_level(buffer);
+        if let Err(e) = self.sender.send(AudioAction::Level(rms, peak)) {
+            error!("SEND ERROR {}", e);
+        }
+
+        if self.buffer_count % self.raw_interval == 0 {
+            //the most recent samples of long buffers
+            let start = buffer.len().saturating_sub(RAW_MAX_SAMPLES * self.raw_factor);
+            let raw = decimate(&buffer[start..], self.raw_factor);
+            let raw_rate = self.sample_rate / self.raw_factor as f64;
+            if let Err(e) = self.sender.send(AudioAction::RawAudio(raw, raw_rate)) {
+                error!("SEND ERROR {}", e);
+            }
+        }
+        self.buffer_count = self.buffer_count.wrapping_add(1);
+
+        let pitch = match self.detector.do_result(buffer) {
+            Ok(pitch) => pitch,
+            Err(e) => {
+                error!("pitch detection error {}", e);
+                return;
+            }
+        };
+        let confidence = self.detector.get_confidence();
+
+        //aubio bugs out sometimes?
+        if pitch < 95999.98 {
+            let duration = buffer.len() as f64 / self.sample_rate;
+            if let Err(e) = self.sender.send(AudioAction::Pitch(pitch, confidence, duration)) {
+                error!("SEND ERROR {}", e);
+            }
+        }
+    }
+}
+
+//values of the audio-backend enum built into this binary, with their names
+pub fn available_backends() -> Vec<(i32, String)> {
+    let mut backends = vec![(0, "PortAudio".to_string())];
+    #[cfg(feature = "pipewire")]
+    backends.push((1, "PipeWire".to_string()));
+    #[cfg(feature = "jack")]
+    backends.push((2, "JACK".to_string()));
+    backends.push((3, i18n("Test Signal")));
+    backends
+}
+
+//the listed device the settings point to, by id only while it still carries the
+//chosen name, an id left from an earlier choice must not win over a new name
+fn find_device(devices: &[InputDevice], id: &str, name: &str) -> InputDevice {
+    devices
+        .iter()
+        .find(|d| d.id == id && d.name == name)
+        .or_else(|| devices.iter().find(|d| d.name == name))
+        .cloned()
+        .unwrap_or_else(|| InputDevice::named(name))
+}
 
 mod imp {
     use super::*;
@@ -62,13 +204,13 @@ mod imp {
 
     #[derive(Debug)]
     pub struct RecorderPriv {
-        pub sample_rate: Cell<i64>, //44800
-        pub buffer_size: Cell<u32>,
         pub pa: RefCell<Option<Rc<portaudio::PortAudio>>>,
         pub sender: RefCell<Option<Sender<AudioAction>>>,
-        
-        pub tx: RefCell<Option<mpsc::Sender<()>>>,
-        // pub rx: RefCell<Option<crossbeam_channel::Receiver<()>>>,
+        pub source: RefCell<Option<Box<dyn AudioSource>>>,
+        pub suspended: Cell<bool>,
+        //name of the device streamed from, None for the backend's default
+        pub device_name: RefCell<Option<String>>,
+        pub synthetic_snr: Snr,
         pub settings: gio::Settings,
     }
 
@@ -79,14 +221,15 @@ mod imp {
         type ParentType = glib::Object;
 
         fn new() -> Self {
+            let settings = util::settings_manager();
             Self {
-                sample_rate: Cell::new(44100),
-                buffer_size: Cell::new(1024),
                 pa: RefCell::new(None),
                 sender: RefCell::new(None),
-                tx: RefCell::new(None),
-                // rx: RefCell::new(None),
-                settings: util::settings_manager(),
+                source: RefCell::new(None),
+                suspended: Cell::new(false),
+                device_name: RefCell::new(None),
+                synthetic_snr: Snr::new(settings.double("synthetic-snr")),
+                settings,
             }
         }
     }
@@ -98,9 +241,15 @@ mod imp {
 
         fn signals() -> &'static [Signal] {
             static SIGNALS: Lazy<Vec<Signal>> = Lazy::new(|| {
-                vec![Signal::builder("frequency")
-                    .param_types([<f32>::static_type()])
-                    .build()]
+                vec![
+                    Signal::builder("frequency")
+                        .param_types([<f32>::static_type()])
+                        .build(),
+                    //rms and peak input level in dBFS
+                    Signal::builder("level")
+                        .param_types([<f32>::static_type(), <f32>::static_type()])
+                        .build(),
+                ]
             });
 
             SIGNALS.as_ref()
@@ -132,273 +281,365 @@ impl Recorder {
 
     pub fn setup(&self) -> Result<(), Box<dyn Error>> {
         let imp = self.imp();
-        
-        //get settings vars
-        let manual: bool = imp.settings.boolean("choose-device");
-        let manual_device_name = imp.settings.string("selected-device").to_string();
 
         // Construct a portaudio instance that will connect to a native audio API
-        imp.pa.replace(Some(Rc::new(
-            portaudio::PortAudio::new().expect("Unable to init PortAudio"),
-        )));
-
-        //if manual set, try to stream with manual
-        if manual && manual_device_name != "" {
-            match self.running_mic_index(manual_device_name.clone()) {
-                // and start the stream with the index
-                Ok(index) => {
-                    debug!("got manual device... {}", manual_device_name);
-                    return self.start_stream(Some(index));
-                },
-                Err(e) => {
-                    error!("unable to retrieve device {}", e);
-                    toasts::add_error_toast(i18n_k("Unable to retrieve device ({device_name})", &[("device_name", &manual_device_name)]));
-                },
-            }
+        match portaudio::PortAudio::new() {
+            Ok(pa) => {
+                imp.pa.replace(Some(Rc::new(pa)));
+            },
+            //only needed by the portaudio backend and the tone generator, the
+            //other backends work without a sound card, e.g. on a headless machine
+            Err(e) => error!("unable to init portaudio: {}", e),
         }
 
-        //try to get running input device from pulse audio
-        match self.running_device() {
-            //if able to retrieve, get the device's index from portaudio
-            Ok(device_name) => {
-                debug!("retrieved {}", device_name);
-
-                match self.running_mic_index(device_name.clone()) {
-                    // and start the stream with the index
-                    Ok(index) => {
-                        debug!("got running device...");
-                        self.start_stream(Some(index))?;
-                        imp.settings.set_string("selected-device", &device_name)?;
-                        return Ok(());
-                    },
-                    Err(e) => {
-                        error!("{},unable to retrieve device ", e);
-                        toasts::add_error_toast(i18n_k("Unable to retrieve device ({device_name})", &[("device_name", &device_name)]));
-                    },
-                }
-            }
-            Err(e) => {
-                error!("{}, starting stream w/ default option", e);
-            }
+        self.switch_stream(None)
+    }
+
+    //the source for the audio-backend setting, falling back to portaudio for
+    //backends this binary was built without
+    fn make_source(&self) -> Result<Box<dyn AudioSource>, Box<dyn Error>> {
+        let settings = &self.imp().settings;
+
+        #[cfg(feature = "pipewire")]
+        if settings.enum_("audio-backend") == 1 {
+            return Ok(Box::new(PipeWireSource::new()));
         }
 
-        //otherwise, just start the stream with the default portaudio option
-        return self.start_stream(None);
+        #[cfg(feature = "jack")]
+        if settings.enum_("audio-backend") == 2 {
+            return Ok(Box::new(JackSource::new(settings.boolean("jack-passthrough"))));
+        }
+
+        if settings.enum_("audio-backend") == 3 {
+            return Ok(Box::new(SyntheticSource::new(self.imp().synthetic_snr.clone())));
+        }
+
+        let pa = self.portaudio().ok_or_else(|| AudioSourceError("PortAudio is not available".into()))?;
+        Ok(Box::new(PortAudioSource::new(pa)))
+    }
+
+    //the running test signal picks this up from its next buffer on
+    pub fn set_synthetic_snr(&self, snr: f64) {
+        self.imp().synthetic_snr.set(snr);
+    }
+
+    //replace the source when the backend setting changed, stopping the old one
+    pub fn switch_backend(&self) -> Result<(), Box<dyn Error>> {
+        let imp = self.imp();
+        if let Some(mut source) = imp.source.take() {
+            source.stop();
+        }
+        self.switch_stream(None)
     }
 
+    //input devices of the current backend
+    pub fn devices(&self) -> Result<Vec<InputDevice>, Box<dyn Error>> {
+        let imp = self.imp();
+        if imp.source.borrow().is_none() {
+            imp.source.replace(Some(self.make_source()?));
+        }
+        imp.source.borrow().as_ref().unwrap().devices()
+    }
 
+    //start streaming from the device shown as device_option. with None the manually
+    //selected device is used if set, otherwise the running one, otherwise the default
     pub fn switch_stream(&self, device_option: Option<String>) -> Result<(), Box<dyn Error>> {
         let imp = self.imp();
 
-        if device_option.is_none() {
-            let manual: bool = imp.settings.boolean("choose-device");
-            let manual_device_name = imp.settings.string("selected-device").to_string();
-
-            if manual && manual_device_name != "" {
-                match self.running_mic_index(manual_device_name.clone()) {
-                    // and start the stream with the index
-                    Ok(index) => {
-                        debug!("switch_stream -> got manual device... {}", manual_device_name);
-                        return self.start_stream(Some(index));
-                    },
-                    Err(e) => {
-                        error!("switch_stream -> unable to retrieve device {}", e);
-                        toasts::add_error_toast(i18n_k("Unable to retrieve device ({device_name})", &[("device_name", &manual_device_name)]));
-                    },
-                }
-            }
-        } 
-
-        let device_name = match device_option {
-            Some(name) => name,
-            None => {
-                match self.running_device() {
-                    //if able to retrieve, get the device's index from portaudio
-                    Ok(name) => {
-                        debug!("switch_stream -> retrieved {}", name);
-                        name
-                    },
-                    Err(e) => {
-                        error!("switch_stream -> {}, starting stream w/ default option", e);
-
-                        //otherwise, just start the stream with the default portaudio option
-                        return self.start_stream(None);
-                    }
-                }
-            },
+        //another source is feeding the window, the stream starts again on resume
+        if imp.suspended.get() {
+            debug!("recorder -> suspended, not starting stream");
+            return Ok(());
+        }
+
+        if imp.source.borrow().is_none() {
+            imp.source.replace(Some(self.make_source()?));
+        }
+        let mut source_ref = imp.source.borrow_mut();
+        let source = source_ref.as_mut().unwrap();
+
+        let manual: bool = imp.settings.boolean("choose-device");
+        let manual_device = InputDevice {
+            id: imp.settings.string("selected-device-id").to_string(),
+            name: imp.settings.string("selected-device").to_string(),
         };
 
-        match self.running_mic_index(device_name.clone()) {
-            // and start the stream with the index
-            Ok(index) => {
-                debug!("switch_stream -> got running device...");
-                self.start_stream(Some(index))?;
-                imp.settings.set_string("selected-device", &device_name)?;
-                return Ok(());
+        let device = match device_option {
+            //look the name up, so backends with stable ids get theirs
+            Some(name) => Some(match source.devices() {
+                Ok(devices) => find_device(&devices, "", &name),
+                Err(_) => InputDevice::named(&name),
+            }),
+            None if manual && manual_device.name != "" => {
+                debug!("switch_stream -> got manual device... {}", manual_device.name);
+                match source.devices() {
+                    Ok(devices) => Some(find_device(&devices, &manual_device.id, &manual_device.name)),
+                    Err(_) if !manual_device.id.is_empty() => Some(manual_device),
+                    Err(_) => Some(InputDevice::named(&manual_device.name)),
+                }
             },
-            Err(e) => {
-                error!("switch_stream -> {},unable to retrieve device ", e);
-                toasts::add_error_toast(i18n_k("Unable to retrieve device ({device_name})", &[("device_name", &device_name)]));
+            None => match source.running_device() {
+                Ok(device) => {
+                    debug!("switch_stream -> retrieved {}", device.name);
+                    Some(device)
+                },
+                Err(e) => {
+                    debug!("switch_stream -> {}, starting stream w/ default option", e);
+                    None
+                },
             },
+        };
+
+        let analysis = AnalysisSettings::load(&imp.settings);
+        let sender = imp.sender.borrow().as_ref().unwrap().clone();
+
+        if let Some(device) = device {
+            match source.start(Some(&device), analysis, sender.clone()) {
+                Ok(_) => {
+                    debug!("switch_stream -> streaming from {}", device.name);
+                    drop(source_ref);
+                    imp.device_name.replace(Some(device.name.clone()));
+                    imp.settings.set_string("selected-device-id", &device.id)?;
+                    imp.settings.set_string("selected-device", &device.name)?;
+                    return Ok(());
+                },
+                Err(e) => {
+                    error!("switch_stream -> {}, unable to retrieve device", e);
+                    toasts::add_error_toast(i18n_k("Unable to retrieve device ({device_name})", &[("device_name", &device.name)]));
+                },
+            }
+        }
+
+        //otherwise, just start the stream with the backend's default
+        imp.device_name.replace(None);
+        source.start(None, analysis, sender)
+    }
+
+    pub fn device_name(&self) -> Option<String> {
+        self.imp().device_name.borrow().clone()
+    }
+
+    //end the running stream while another source feeds the window
+    pub fn suspend(&self) {
+        debug!("recorder -> suspend");
+        let imp = self.imp();
+        imp.suspended.set(true);
+        if let Some(source) = imp.source.borrow_mut().as_mut() {
+            source.stop();
         }
+    }
 
+    //listen to the input device again
+    pub fn resume(&self) -> Result<(), Box<dyn Error>> {
+        debug!("recorder -> resume");
+        self.imp().suspended.set(false);
+        self.switch_stream(None)
+    }
 
-        //otherwise, just start the stream with the default portaudio option
-        return self.start_stream(None);
+    //a sender to the window, for other audio sources
+    pub fn sender(&self) -> Option<Sender<AudioAction>> {
+        self.imp().sender.borrow().clone()
     }
 
-    fn running_device(&self) -> Result<String, Box<dyn Error>> {
-        let mut handler = SourceController::create().unwrap();
-        for device in handler.list_devices()? {
-            if device.state == pulsectl::controllers::types::DevState::Running {
-                debug!("Pulse Audio Source: {}", device.name.unwrap());
-                return Ok(device.description.unwrap().to_owned());
+    //the portaudio instance, shared with the tone generator. None until setup
+    pub fn portaudio(&self) -> Option<Rc<portaudio::PortAudio>> {
+        self.imp().pa.borrow().clone()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use std::time::{Duration, Instant};
+
+    const SAMPLE_RATE: f64 = 44100.0;
+
+    //hands fixed buffers to the analyser as soon as it is started
+    #[derive(Debug)]
+    struct BufferSource {
+        buffers: Vec<Vec<f32>>,
+    }
+
+    impl AudioSource for BufferSource {
+        fn devices(&self) -> Result<Vec<InputDevice>, Box<dyn Error>> {
+            Ok(vec![InputDevice::named("buffers")])
+        }
+
+        fn start(&mut self, _device: Option<&InputDevice>, analysis: AnalysisSettings, sender: Sender<AudioAction>) -> Result<(), Box<dyn Error>> {
+            let mut analyser = Analyser::new(analysis, SAMPLE_RATE, sender)?;
+            for buffer in self.buffers.iter() {
+                analyser.process(buffer);
             }
+            Ok(())
         }
-        return Err(Box::new(RecorderError("No devices found".into())));
-    }
-
-    fn running_mic_index(&self, device_name: String) -> Result<portaudio::DeviceIndex, Box<dyn Error>> {
-        let mut ratio = 0;
-        let mut mic_index = self.pa().default_input_device()?;
-        let matcher = SkimMatcherV2::default();
-
-        for device in self.pa().devices()? {
-            let (idx, info) = device?;
-            let in_channels = info.max_input_channels;
-
-            if in_channels > 0 {
-                
-                match matcher.fuzzy_match(&device_name, &info.name) {
-                    Some(val) => {
-                        if val > ratio {
-                            ratio = val;
-                            mic_index = idx;
-                        }
-                    }
-                    None => (),
-                }
+
+        fn stop(&mut self) {}
+    }
+
+    fn analysis() -> AnalysisSettings {
+        AnalysisSettings {
+            algorithm: 0,
+            silence: -90.0,
+            buffer_size: 2048,
+        }
+    }
+
+    fn sine(frequency: f32, amplitude: f32, buffers: usize) -> Vec<Vec<f32>> {
+        let size = analysis().buffer_size as usize;
+        (0..buffers)
+            .map(|b| {
+                (0..size)
+                    .map(|i| {
+                        let t = (b * size + i) as f32 / SAMPLE_RATE as f32;
+                        amplitude * (2.0 * PI * frequency * t).sin()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    //start the source and collect what the window would be sent, until done
+    //says enough has arrived or the timeout passes
+    fn run(source: &mut dyn AudioSource, device: Option<&InputDevice>, done: impl Fn(&[AudioAction]) -> bool) -> Vec<AudioAction> {
+        let context = glib::MainContext::new();
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        let actions = Rc::new(RefCell::new(Vec::new()));
+
+        receiver.attach(Some(&context), {
+            let actions = actions.clone();
+            move |action| {
+                actions.borrow_mut().push(action);
+                glib::Continue(true)
+            }
+        });
+
+        source.start(device, analysis(), sender).unwrap();
+
+        let start = Instant::now();
+        while !done(&actions.borrow()) && start.elapsed() < Duration::from_secs(10) {
+            if !context.iteration(false) {
+                std::thread::sleep(Duration::from_millis(10));
             }
         }
+        source.stop();
 
-        debug!("{:#?} {}", &mic_index, ratio);
+        let actions = actions.borrow().clone();
+        actions
+    }
 
-        Ok(mic_index)
+    fn pitches(actions: &[AudioAction]) -> Vec<f32> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                AudioAction::Pitch(pitch, _, _) => Some(*pitch),
+                _ => None,
+            })
+            .collect()
     }
 
+    fn levels(actions: &[AudioAction]) -> Vec<(f32, f32)> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                AudioAction::Level(rms, peak) => Some((*rms, *peak)),
+                _ => None,
+            })
+            .collect()
+    }
 
+    #[test]
+    fn test_buffer_level() {
+        let (rms, peak) = buffer_level(&[0.5, -0.5, 0.5, -0.5]);
+        assert!((rms - -6.0206).abs() < 1e-3);
+        assert!((peak - -6.0206).abs() < 1e-3);
 
-    pub fn start_stream(&self, mic_option: Option<portaudio::DeviceIndex>) -> Result<(), Box<dyn Error>> {
-        // MANY THANKS TO https://dev.to/maniflames/audio-visualization-with-rust-4nhg
-        
-        let mic_index = match mic_option {
-            Some(index) => index,
-            None => self.pa().default_input_device()?,
-        };
-        
-        debug!("recorder -> start stream {:?}", mic_index);
+        let (rms, peak) = buffer_level(&[]);
+        assert_eq!(rms, -f32::INFINITY);
+        assert_eq!(peak, -f32::INFINITY);
+    }
 
-        let imp = self.imp();
-        let default_low_input_latency = self.pa().device_info(mic_index)?.default_low_input_latency;
-        let default_sample_rate = self.pa().device_info(mic_index)?.default_sample_rate;
-
-        // Set parameters for the stream settings.
-        // We pass which mic should be used, how many channels are used,
-        // whether all the values of all the channels should be passed in a
-        // single audiobuffer and the latency that should be considered
-        let input_params =
-            portaudio::StreamParameters::<f32>::new(mic_index, 1, true, default_low_input_latency);
-
-        // Settings for an inputstream.
-        // Here we pass the stream parameters we set before,
-        // the sample rate of the mic and the amount values we want to receive
-        
-        let buffer_size_setting: u32 = imp.settings.double("buffer-size") as u32;
-        let buffer_size: u32 = 1024 * (buffer_size_setting / 1024);
-
-        debug!("buffer size {}", buffer_size);
-
-        let input_settings =
-            portaudio::InputStreamSettings::new(input_params, default_sample_rate, buffer_size);
-
-        // Creating a channel so we can receive audio values asynchronously
-        let (sender, receiver) = channel();
-        
-        // Additional channel to kill thread when switching devices
-        //let (tx, rx) = crossbeam_channel::unbounded::<()>();
-        let (tx, rx) = mpsc::channel::<()>();
-
-        if !imp.tx.borrow().as_ref().is_none() {
-            debug!("killing previous thread with drop send");
-            drop(imp.tx.borrow().as_ref()); //drop should kill previous stream 
+    #[test]
+    fn test_decimate() {
+        assert_eq!(decimate(&[1.0, 3.0, 2.0, 4.0, 9.0], 2), [2.0, 3.0]);
+        assert_eq!(decimate(&[1.0, 2.0], 1), [1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_find_device() {
+        let devices = [
+            InputDevice { id: "1".into(), name: "Vibrato A3".into() },
+            InputDevice { id: "2".into(), name: "Sine A4".into() },
+            InputDevice { id: "3".into(), name: "Sine A4".into() },
+        ];
+
+        //a stale id from the last session does not override a new name
+        assert_eq!(find_device(&devices, "1", "Sine A4"), devices[1]);
+        //the id picks between devices sharing a name
+        assert_eq!(find_device(&devices, "3", "Sine A4"), devices[2]);
+        assert_eq!(find_device(&devices, "", "Vibrato A3"), devices[0]);
+        assert_eq!(find_device(&devices, "1", "Square A4"), InputDevice::named("Square A4"));
+    }
+
+    #[test]
+    fn test_raw_audio() {
+        let mut source = BufferSource { buffers: sine(440.0, 0.5, 1) };
+        let actions = run(&mut source, None, |actions| !pitches(actions).is_empty());
+
+        let raw: Vec<(usize, f64)> = actions
+            .iter()
+            .filter_map(|action| match action {
+                AudioAction::RawAudio(buffer, rate) => Some((buffer.len(), *rate)),
+                _ => None,
+            })
+            .collect();
+        //44.1 kHz halved, still above twice the top of the spectrum
+        assert_eq!(raw, [(1024, 22050.0)]);
+    }
+
+    #[test]
+    fn test_analyser_sine() {
+        let mut source = BufferSource { buffers: sine(440.0, 0.5, 8) };
+        let actions = run(&mut source, None, |actions| pitches(actions).len() == 8);
+
+        let levels = levels(&actions);
+        assert_eq!(levels.len(), 8);
+        for (rms, peak) in levels {
+            //a sine's rms is 3 dB below its peak
+            assert!((rms - -9.03).abs() < 0.05, "rms {}", rms);
+            assert!((peak - -6.02).abs() < 0.05, "peak {}", peak);
         }
-        imp.tx.replace(Some(tx));
-
-        // A callback function that should be as short as possible so we send all the info to a different thread
-        let callback =
-            move | portaudio::InputStreamCallbackArgs { buffer, .. } | match sender.send(buffer) {
-                Ok(_) => portaudio::Continue,
-                Err(_) => portaudio::Complete,
-            };
-
-        // Creating & starting the input stream with our settings & callback
-        let mut stream = self
-            .pa()
-            .open_non_blocking_stream(input_settings, callback)?;
-
-        stream.start()?;
-        
-        //Printing values every time we receive new ones while the stream is active
-        let glib_sender = imp.sender.borrow().as_ref().unwrap().clone();
-
-        //RECEIVE AUDIO BUFFER AND SEND TO GLIB LOOP
-        thread::spawn(move || {
-            let mut pitch_detector = Pitch::new(
-                PitchMode::Yin,
-                buffer_size as usize,
-                buffer_size as usize / 2,
-                default_sample_rate as u32,
-            )
-            .unwrap();
-
-            debug!("recorder -> stream thread");
-
-            while stream.is_active().unwrap() {
-                while let Ok(buffer) = receiver.try_recv() {
-                    let pitch = pitch_detector.do_result(&buffer).unwrap();
-
-                    //aubio bugs out sometimes?
-                    if pitch < 95999.98 {
-                        match glib_sender.send(AudioAction::Pitch(pitch)) {
-                            Ok(_) => (),
-                            Err(e) => {
-                                error!("SEND ERROR {}", e);
-                            }
-                        }
-                    }
 
+        let pitches = pitches(&actions);
+        assert_eq!(pitches.len(), 8);
+        assert!((pitches[7] - 440.0).abs() < 1.0, "pitch {}", pitches[7]);
+    }
 
-                }
-                
-                //kill stream thread if channel disconnect
-                match rx.try_recv() {
-                    Ok(_) | Err(mpsc::TryRecvError::Disconnected) => {
-                        error!("disconnected channel, stream should end");
-                        break;
-                    }
-                    Err(mpsc::TryRecvError::Empty) => {}
-                }
+    #[test]
+    fn test_analyser_silence() {
+        let mut source = BufferSource { buffers: vec![vec![0.0; 2048]; 4] };
+        let actions = run(&mut source, None, |actions| pitches(actions).len() == 4);
 
-            }
+        assert!(levels(&actions).iter().all(|(rms, _)| *rms == -f32::INFINITY));
+        assert!(pitches(&actions).iter().all(|pitch| *pitch == 0.0));
+    }
 
-            debug!("stream closing ...");
-            stream.close().unwrap();
-        });
+    #[test]
+    fn test_synthetic_source() {
+        let mut source = SyntheticSource::new(Snr::new(40.0));
+        let device = InputDevice::named("Sine A4");
+        let heard = |actions: &[AudioAction]| pitches(actions).iter().any(|pitch| (pitch - 440.0).abs() < 2.0);
 
-        Ok(())
+        let actions = run(&mut source, Some(&device), heard);
+        assert!(heard(&actions), "pitches {:?}", pitches(&actions));
+        assert!(!levels(&actions).is_empty());
     }
 
-    fn pa(&self) -> Rc<portaudio::PortAudio> {
-        self.imp().pa.borrow().as_ref().unwrap().clone()
+    #[test]
+    fn test_synthetic_unknown_signal() {
+        let mut source = SyntheticSource::new(Snr::new(40.0));
+        let (sender, _receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        assert!(source.start(Some(&InputDevice::named("Square Z9")), analysis(), sender).is_err());
     }
 }
@@ -0,0 +1,449 @@
+/* scala.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//Scala tuning files, see https://www.huygens-fokker.org/scala/scl_format.html
+//and https://www.huygens-fokker.org/scala/help.htm#mappings
+
+use std::{cell::RefCell, collections::HashMap, error::Error, fmt, fs};
+use std::path::{Path, PathBuf};
+use std::thread::LocalKey;
+use std::time::SystemTime;
+
+use super::tuning::{Note, NOTE_NAMES};
+
+//parsed files by path and modification time, the tuning is loaded again on
+//every settings change but the files seldom change
+type Cache<T> = RefCell<HashMap<PathBuf, (SystemTime, T)>>;
+
+thread_local! {
+    static SCALES: Cache<Scale> = RefCell::new(HashMap::new());
+    static MAPPINGS: Cache<KeyboardMapping> = RefCell::new(HashMap::new());
+}
+
+fn load_cached<T: Clone>(
+    cache: &'static LocalKey<Cache<T>>,
+    path: &Path,
+    parse: fn(&str) -> Result<T, ScalaError>,
+) -> Result<T, Box<dyn Error>> {
+    let modified = fs::metadata(path)?.modified().ok();
+
+    if let Some(modified) = modified {
+        let cached = cache.with(|c| match c.borrow().get(path) {
+            Some((time, value)) if *time == modified => Some(value.clone()),
+            _ => None,
+        });
+        if let Some(value) = cached {
+            return Ok(value);
+        }
+    }
+
+    let value = parse(&fs::read_to_string(path)?)?;
+    //without a modification time a change could not be noticed, so nothing is kept
+    if let Some(modified) = modified {
+        cache.with(|c| c.borrow_mut().insert(path.to_path_buf(), (modified, value.clone())));
+    }
+    Ok(value)
+}
+
+#[derive(Debug)]
+pub struct ScalaError(String);
+
+impl fmt::Display for ScalaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ScalaError {}
+
+//non comment lines of a scala file, paired with their line number
+fn lines(contents: &str) -> impl Iterator<Item = (usize, &str)> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.starts_with('!'))
+}
+
+fn next_line<'a>(lines: &mut impl Iterator<Item = (usize, &'a str)>, what: &str) -> Result<(usize, &'a str), ScalaError> {
+    lines
+        .next()
+        .ok_or_else(|| ScalaError(format!("unexpected end of file, expected {}", what)))
+}
+
+//only the first word of a line is significant, the rest may be a comment
+fn first_word(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+fn parse_int(line: (usize, &str), what: &str) -> Result<i32, ScalaError> {
+    first_word(line.1)
+        .parse::<i32>()
+        .map_err(|_| ScalaError(format!("line {}: invalid {} \"{}\"", line.0, what, line.1)))
+}
+
+//a pitch is either cents (contains a period) or a ratio, a lone integer n meaning n/1
+fn parse_pitch(line: (usize, &str)) -> Result<f64, ScalaError> {
+    let word = first_word(line.1);
+    let invalid = || ScalaError(format!("line {}: invalid pitch \"{}\"", line.0, line.1));
+
+    if word.contains('.') {
+        return word.parse::<f64>().map_err(|_| invalid());
+    }
+
+    let (numerator, denominator) = match word.split_once('/') {
+        Some((n, d)) => (n.parse::<f64>().map_err(|_| invalid())?, d.parse::<f64>().map_err(|_| invalid())?),
+        None => (word.parse::<f64>().map_err(|_| invalid())?, 1.0),
+    };
+
+    if numerator <= 0.0 || denominator <= 0.0 {
+        return Err(invalid());
+    }
+
+    Ok(1200.0 * (numerator / denominator).log2())
+}
+
+#[derive(Clone, Debug)]
+pub struct Scale {
+    pub description: String,
+    //pitches of the degrees above the unison in cents, the last one is the period
+    pub pitches: Vec<f64>,
+}
+
+impl Scale {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Scale, Box<dyn Error>> {
+        load_cached(&SCALES, path.as_ref(), Self::parse)
+    }
+
+    pub fn parse(contents: &str) -> Result<Scale, ScalaError> {
+        let mut lines = lines(contents);
+
+        let description = next_line(&mut lines, "a description")?.1.to_string();
+        let count = parse_int(next_line(&mut lines, "the number of notes")?, "number of notes")?;
+
+        if count < 1 {
+            return Err(ScalaError("scale has no notes".into()));
+        }
+
+        let mut pitches = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            pitches.push(parse_pitch(next_line(&mut lines, "a pitch")?)?);
+        }
+
+        if *pitches.last().unwrap() <= 0.0 {
+            return Err(ScalaError("scale period must be above the unison".into()));
+        }
+
+        Ok(Scale { description, pitches })
+    }
+
+    pub fn len(&self) -> i32 {
+        self.pitches.len() as i32
+    }
+
+    pub fn period(&self) -> f64 {
+        *self.pitches.last().unwrap()
+    }
+
+    //pitch in cents of any degree, degree 0 being the unison
+    fn degree_cents(&self, degree: i32) -> f64 {
+        let periods = degree.div_euclid(self.len());
+        let index = degree.rem_euclid(self.len());
+        let within = match index {
+            0 => 0.0,
+            i => self.pitches[i as usize - 1],
+        };
+        periods as f64 * self.period() + within
+    }
+
+    //find the scale degree closest to frequency. without a keyboard mapping the
+    //unison sits on middle C, derived from the reference pitch
+    pub fn nearest_note(&self, mapping: Option<&KeyboardMapping>, frequency: f64, reference: f64) -> Note {
+        let mapping = match mapping {
+            Some(mapping) => mapping.clone(),
+            None => KeyboardMapping::linear(reference),
+        };
+
+        //frequency of degree 0, found from the mapping's reference note. parse
+        //rejects mappings that leave the reference note unmapped
+        let reference_degree = mapping.degree(self, mapping.reference_note).unwrap();
+        let base = mapping.reference_frequency / 2.0_f64.powf(self.degree_cents(reference_degree) / 1200.0);
+
+        let position = 1200.0 * (frequency / base).log2();
+        let periods = (position / self.period()).floor() as i32;
+
+        //candidates are the mapped degrees of the surrounding periods
+        let mapped = mapping.mapped_degrees(self);
+        let (degree, target) = (periods - 1..periods + 2)
+            .flat_map(|p| mapped.iter().map(move |d| d + p * self.len()))
+            .map(|degree| (degree, self.degree_cents(degree)))
+            .min_by(|a, b| (position - a.1).abs().total_cmp(&(position - b.1).abs()))
+            .unwrap_or((0, 0.0));
+
+        //name the degree after the key it is mapped to when that key has a conventional name
+        let (name, octave) = match mapping.key(self, degree) {
+            Some(key) if self.len() == 12 || mapping.size > 0 => {
+                (NOTE_NAMES[key.rem_euclid(12) as usize].to_string(), key.div_euclid(12) - 1)
+            }
+            _ => (format!("{}", degree.rem_euclid(self.len())), 4 + degree.div_euclid(self.len())),
+        };
+
+        Note {
+            name,
+            octave,
+            frequency: base * 2.0_f64.powf(target / 1200.0),
+            cents: position - target,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct KeyboardMapping {
+    pub size: i32,
+    pub first_note: i32,
+    pub last_note: i32,
+    pub middle_note: i32,
+    pub reference_note: i32,
+    pub reference_frequency: f64,
+    pub octave_degree: i32,
+    //scale degree of each key in the pattern, None if the key is unmapped
+    pub map: Vec<Option<i32>>,
+}
+
+impl KeyboardMapping {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<KeyboardMapping, Box<dyn Error>> {
+        load_cached(&MAPPINGS, path.as_ref(), Self::parse)
+    }
+
+    pub fn parse(contents: &str) -> Result<KeyboardMapping, ScalaError> {
+        let mut lines = lines(contents).filter(|(_, line)| !line.is_empty());
+
+        let size = parse_int(next_line(&mut lines, "the map size")?, "map size")?;
+        let first_note = parse_int(next_line(&mut lines, "the first note")?, "first note")?;
+        let last_note = parse_int(next_line(&mut lines, "the last note")?, "last note")?;
+        let middle_note = parse_int(next_line(&mut lines, "the middle note")?, "middle note")?;
+        let reference_note = parse_int(next_line(&mut lines, "the reference note")?, "reference note")?;
+
+        let line = next_line(&mut lines, "the reference frequency")?;
+        let reference_frequency = first_word(line.1)
+            .parse::<f64>()
+            .ok()
+            .filter(|f| *f > 0.0)
+            .ok_or_else(|| ScalaError(format!("line {}: invalid reference frequency \"{}\"", line.0, line.1)))?;
+
+        let octave_degree = parse_int(next_line(&mut lines, "the formal octave degree")?, "formal octave degree")?;
+
+        if size < 0 || first_note > last_note {
+            return Err(ScalaError("invalid keyboard range".into()));
+        }
+
+        let mut map = Vec::with_capacity(size as usize);
+        for _ in 0..size {
+            let line = next_line(&mut lines, "a key mapping")?;
+            if first_word(line.1) == "x" {
+                map.push(None);
+            } else {
+                map.push(Some(parse_int(line, "key mapping")?));
+            }
+        }
+
+        //the reference frequency would belong to no degree at all
+        if size > 0 && map[(reference_note - middle_note).rem_euclid(size) as usize].is_none() {
+            return Err(ScalaError(format!("reference note {} is not mapped", reference_note)));
+        }
+
+        Ok(KeyboardMapping {
+            size,
+            first_note,
+            last_note,
+            middle_note,
+            reference_note,
+            reference_frequency,
+            octave_degree,
+            map,
+        })
+    }
+
+    //the scala default mapping, one key per degree starting at middle C
+    fn linear(reference: f64) -> KeyboardMapping {
+        KeyboardMapping {
+            size: 0,
+            first_note: 0,
+            last_note: 127,
+            middle_note: 60,
+            reference_note: 60,
+            reference_frequency: reference * 2.0_f64.powf(-9.0 / 12.0),
+            octave_degree: 0,
+            map: Vec::new(),
+        }
+    }
+
+    //scale degree a key plays, relative to the middle note
+    fn degree(&self, scale: &Scale, key: i32) -> Option<i32> {
+        let offset = key - self.middle_note;
+        if self.size == 0 {
+            return Some(offset);
+        }
+
+        let octave_degree = match self.octave_degree {
+            0 => scale.len(),
+            d => d,
+        };
+
+        self.map[offset.rem_euclid(self.size) as usize].map(|d| d + offset.div_euclid(self.size) * octave_degree)
+    }
+
+    fn key(&self, scale: &Scale, degree: i32) -> Option<i32> {
+        (self.first_note..self.last_note + 1).find(|key| self.degree(scale, *key) == Some(degree))
+    }
+
+    //degrees within one period that any key plays
+    fn mapped_degrees(&self, scale: &Scale) -> Vec<i32> {
+        if self.size == 0 {
+            return (0..scale.len()).collect();
+        }
+
+        let mut degrees: Vec<i32> = self.map.iter().flatten().map(|d| d.rem_euclid(scale.len())).collect();
+        degrees.sort_unstable();
+        degrees.dedup();
+        degrees
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCALE: &str = "! meantone.scl
+!
+Pythagorean fragment
+ 4
+!
+ 100.0 a cents value
+ 3/2
+   ! an indented comment
+ 16/9
+ 2
+";
+
+    const MAPPING: &str = "! a mapping that leaves out the black keys
+12
+0
+127
+60
+69
+440.0
+7
+! the pattern
+0
+x
+1
+x
+2
+3
+x
+4
+x
+5
+x
+6
+";
+
+    #[test]
+    fn test_parse_scale() {
+        let scale = Scale::parse(SCALE).unwrap();
+        assert_eq!(scale.description, "Pythagorean fragment");
+        assert_eq!(scale.len(), 4);
+        assert!((scale.pitches[0] - 100.0).abs() < 1e-9);
+        assert!((scale.pitches[1] - 701.955).abs() < 1e-3);
+        assert!((scale.pitches[2] - 996.090).abs() < 1e-3);
+        assert!((scale.period() - 1200.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parse_scale_errors() {
+        //too few pitches
+        assert!(Scale::parse("short\n3\n100.0\n2/1\n").is_err());
+        //not a number of notes
+        assert!(Scale::parse("bad count\nmany\n2/1\n").is_err());
+        assert!(Scale::parse("no notes\n0\n").is_err());
+        //not a pitch
+        assert!(Scale::parse("bad pitch\n1\nfifth\n").is_err());
+        assert!(Scale::parse("bad ratio\n1\n3/0\n").is_err());
+        assert!(Scale::parse("negative ratio\n1\n-3/2\n").is_err());
+        //the period has to be above the unison
+        assert!(Scale::parse("falling\n1\n-1200.0\n").is_err());
+        assert!(Scale::parse("").is_err());
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        let mapping = KeyboardMapping::parse(MAPPING).unwrap();
+        assert_eq!(mapping.size, 12);
+        assert_eq!(mapping.middle_note, 60);
+        assert_eq!(mapping.reference_note, 69);
+        assert_eq!(mapping.reference_frequency, 440.0);
+        assert_eq!(mapping.octave_degree, 7);
+        assert_eq!(mapping.map[0], Some(0));
+        assert_eq!(mapping.map[1], None);
+        assert_eq!(mapping.map[11], Some(6));
+    }
+
+    #[test]
+    fn test_parse_mapping_errors() {
+        //the reference note, A, is left unmapped
+        let unmapped = MAPPING.replace("\n5\nx\n6\n", "\nx\nx\n6\n");
+        assert!(KeyboardMapping::parse(&unmapped).is_err());
+
+        let frequency = MAPPING.replace("440.0", "-440.0");
+        assert!(KeyboardMapping::parse(&frequency).is_err());
+
+        let range = MAPPING.replacen("\n127\n", "\n-1\n", 1);
+        assert!(KeyboardMapping::parse(&range).is_err());
+
+        //a key missing from the pattern
+        let short = MAPPING.trim_end().trim_end_matches('6');
+        assert!(KeyboardMapping::parse(short).is_err());
+    }
+
+    #[test]
+    fn test_nearest_note() {
+        let equal = Scale::parse(&format!(
+            "12 equal\n12\n{}\n",
+            (1..13).map(|i| format!("{}.0", i * 100)).collect::<Vec<String>>().join("\n")
+        ))
+        .unwrap();
+
+        //without a mapping the scale sits on middle C, from the reference pitch
+        let note = equal.nearest_note(None, 440.0, 440.0);
+        assert_eq!((note.name.as_str(), note.octave), ("A", 4));
+        assert!(note.cents.abs() < 1e-9);
+
+        let note = equal.nearest_note(None, 261.63, 440.0);
+        assert_eq!((note.name.as_str(), note.octave), ("C", 4));
+        assert!((note.cents - 0.0).abs() < 0.1);
+
+        //the reference key plays the reference frequency, and is named after the key
+        let mapping = KeyboardMapping::parse(MAPPING).unwrap();
+        let note = equal.nearest_note(Some(&mapping), 440.0, 440.0);
+        assert_eq!((note.name.as_str(), note.octave), ("A", 4));
+        assert!((note.frequency - 440.0).abs() < 1e-9);
+    }
+}
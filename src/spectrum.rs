@@ -0,0 +1,285 @@
+/* spectrum.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+
+use gtk::{cairo, glib, glib::clone};
+
+use std::{cell::{Cell, RefCell}, error::Error, f64::consts::PI};
+
+use log::error;
+
+use super::util;
+
+use super::tuning::NOTE_NAMES;
+
+//frequency range of the horizontal axis
+const LOW_FREQUENCY: f64 = 27.5;
+const HIGH_FREQUENCY: f64 = 8000.0;
+//magnitude range of the vertical axis in dB
+const FLOOR_DB: f64 = -100.0;
+const CEILING_DB: f64 = 0.0;
+//harmonics marked above the fundamental
+const HARMONICS: i32 = 8;
+
+//in place iterative radix-2 fft, the length must be a power of two
+fn fft(real: &mut [f64], imag: &mut [f64]) {
+    let n = real.len();
+
+    //bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            real.swap(i, j);
+            imag.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let tr = real[b] * cos - imag[b] * sin;
+                let ti = real[b] * sin + imag[b] * cos;
+                real[b] = real[a] - tr;
+                imag[b] = imag[a] - ti;
+                real[a] += tr;
+                imag[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+//magnitudes in dBFS of the positive frequency bins of a hann windowed buffer
+pub fn magnitude_spectrum(buffer: &[f32]) -> Vec<f64> {
+    let n = buffer.len().next_power_of_two();
+    let mut real = vec![0.0; n];
+    let mut imag = vec![0.0; n];
+
+    for (i, sample) in buffer.iter().enumerate() {
+        let window = 0.5 - 0.5 * (2.0 * PI * i as f64 / buffer.len() as f64).cos();
+        real[i] = *sample as f64 * window;
+    }
+
+    fft(&mut real, &mut imag);
+
+    //the hann window halves the amplitude, a full scale sine reads 0 dB
+    let scale = 4.0 / buffer.len() as f64;
+    (0..n / 2)
+        .map(|i| 20.0 * (scale * (real[i] * real[i] + imag[i] * imag[i]).sqrt()).max(1e-10).log10())
+        .collect()
+}
+
+mod imp {
+    use super::*;
+
+    #[derive(Debug)]
+    pub struct Spectrum {
+        pub drawing_area: gtk::DrawingArea,
+        pub magnitudes: RefCell<Vec<f64>>,
+        pub sample_rate: Cell<f64>,
+        pub fundamental: Cell<Option<f64>>,
+        pub reference: Cell<f64>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for Spectrum {
+        const NAME: &'static str = "Spectrum";
+        type Type = super::Spectrum;
+        type ParentType = adw::Bin;
+
+        fn new() -> Self {
+            Self {
+                drawing_area: gtk::DrawingArea::new(),
+                magnitudes: RefCell::new(Vec::new()),
+                sample_rate: Cell::new(44100.0),
+                fundamental: Cell::new(None),
+                reference: Cell::new(440.0),
+            }
+        }
+    }
+
+    impl ObjectImpl for Spectrum {
+        fn constructed(&self) {
+            self.parent_constructed();
+        }
+    }
+
+    impl WidgetImpl for Spectrum {}
+    impl BinImpl for Spectrum {}
+}
+
+glib::wrapper! {
+    pub struct Spectrum(ObjectSubclass<imp::Spectrum>)
+        @extends gtk::Widget, adw::Bin;
+}
+
+impl Spectrum {
+    pub fn new() -> Spectrum {
+        let object: Spectrum = glib::Object::builder::<Spectrum>().build();
+        object.construct();
+        object
+    }
+
+    fn construct(&self) {
+        let imp = self.imp();
+
+        self.set_hexpand(true);
+        self.set_vexpand(true);
+        self.set_halign(gtk::Align::Fill);
+        self.set_valign(gtk::Align::Fill);
+
+        imp.drawing_area.set_hexpand(true);
+        imp.drawing_area.set_vexpand(true);
+        imp.drawing_area
+            .set_draw_func(clone!(@strong self as this => move |_, context, _, _| {
+                match this.draw(context) {
+                    Ok(_) => (),
+                    Err(e) => {
+                        error!("{}", e);
+                    },
+                }
+            }));
+
+        self.set_child(Some(&imp.drawing_area));
+    }
+
+    pub fn set_buffer(&self, buffer: &[f32], sample_rate: f64) {
+        let imp = self.imp();
+        imp.magnitudes.replace(magnitude_spectrum(buffer));
+        imp.sample_rate.set(sample_rate);
+        imp.drawing_area.queue_draw();
+    }
+
+    //the detected pitch, None when nothing is heard
+    pub fn set_fundamental(&self, frequency: Option<f64>) {
+        self.imp().fundamental.set(frequency);
+    }
+
+    //reference pitch of A4, used to place the note grid
+    pub fn set_reference(&self, reference: f64) {
+        self.imp().reference.set(reference);
+        self.imp().drawing_area.queue_draw();
+    }
+
+    fn draw(&self, context: &cairo::Context) -> Result<(), Box<dyn Error>> {
+        let imp = self.imp();
+
+        let spectrum_color = "#364da5";
+        let grid_color = "#7486CC";
+        let fundamental_color = "#ABE8FF";
+        let harmonic_color = "#FFD2C4";
+
+        let width = self.width() as f64;
+        let height = self.height() as f64;
+
+        let x = |frequency: f64| width * (frequency / LOW_FREQUENCY).log2() / (HIGH_FREQUENCY / LOW_FREQUENCY).log2();
+        let y = |db: f64| height * (1.0 - (db.clamp(FLOOR_DB, CEILING_DB) - FLOOR_DB) / (CEILING_DB - FLOOR_DB));
+
+        //a line on every C and a fainter one on every other note
+        context.set_font_size(10.0);
+        let color = util::hex_to_rgb(grid_color);
+        let reference = imp.reference.get();
+        for step in 21..=120 {
+            let frequency = reference * 2.0_f64.powf((step - 69) as f64 / 12.0);
+            if frequency < LOW_FREQUENCY || frequency > HIGH_FREQUENCY {
+                continue;
+            }
+
+            let c = step % 12 == 0;
+            context.set_source_rgba(color.0, color.1, color.2, if c { 0.5 } else { 0.12 });
+            context.set_line_width(1.0);
+            context.move_to(x(frequency), 0.0);
+            context.line_to(x(frequency), height);
+            context.stroke()?;
+
+            if c {
+                context.move_to(x(frequency) + 2.0, height - 4.0);
+                context.show_text(&format!("{}{}", NOTE_NAMES[0], step / 12 - 1))?;
+            }
+        }
+
+        //fundamental and harmonics markers
+        if let Some(fundamental) = imp.fundamental.get() {
+            context.set_line_width(2.0);
+            for harmonic in (1..=HARMONICS).rev() {
+                let frequency = fundamental * harmonic as f64;
+                if frequency > HIGH_FREQUENCY {
+                    continue;
+                }
+
+                let color = match harmonic {
+                    1 => util::hex_to_rgb(fundamental_color),
+                    _ => util::hex_to_rgb(harmonic_color),
+                };
+                context.set_source_rgb(color.0, color.1, color.2);
+                context.move_to(x(frequency), 0.0);
+                context.line_to(x(frequency), height);
+                context.stroke()?;
+
+                context.move_to(x(frequency) + 2.0, 12.0);
+                context.show_text(&format!("{}", harmonic))?;
+            }
+        }
+
+        //the spectrum itself
+        let magnitudes = imp.magnitudes.borrow();
+        if magnitudes.len() < 2 {
+            return Ok(());
+        }
+
+        let bin_width = imp.sample_rate.get() / (2.0 * magnitudes.len() as f64);
+        let color = util::hex_to_rgb(spectrum_color);
+        context.set_source_rgb(color.0, color.1, color.2);
+        context.set_line_width(1.5);
+
+        let mut started = false;
+        for (bin, db) in magnitudes.iter().enumerate().skip(1) {
+            let frequency = bin as f64 * bin_width;
+            if frequency < LOW_FREQUENCY {
+                continue;
+            }
+            if frequency > HIGH_FREQUENCY {
+                break;
+            }
+
+            if started {
+                context.line_to(x(frequency), y(*db));
+            } else {
+                context.move_to(x(frequency), y(*db));
+                started = true;
+            }
+        }
+        context.stroke()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+/* strobe.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+
+use gtk::{cairo, gdk, glib, glib::clone};
+
+use std::{cell::Cell, error::Error};
+
+use log::error;
+
+use super::util;
+
+//stripe periods the pattern drifts per second for each cent of deviation
+const DRIFT_PER_CENT: f64 = 0.1;
+//deviations beyond this are shown at the same speed, faster is unreadable
+const MAX_CENTS: f64 = 50.0;
+//deviations below this count as in tune and change the colour of the pattern,
+//which keeps drifting so sub cent deviations stay visible
+const IN_TUNE_CENTS: f64 = 1.0;
+//number of bands, each one with stripes half as wide as the one above
+const BANDS: i32 = 3;
+
+mod imp {
+    use super::*;
+
+    #[derive(Debug)]
+    pub struct Strobe {
+        pub drawing_area: gtk::DrawingArea,
+        pub cents: Cell<Option<f64>>, //None while no pitch is detected
+        pub phase: Cell<f64>, //offset of the pattern in stripe periods
+        pub last_frame: Cell<Option<i64>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for Strobe {
+        const NAME: &'static str = "Strobe";
+        type Type = super::Strobe;
+        type ParentType = adw::Bin;
+
+        fn new() -> Self {
+            Self {
+                drawing_area: gtk::DrawingArea::new(),
+                cents: Cell::new(None),
+                phase: Cell::new(0.0),
+                last_frame: Cell::new(None),
+            }
+        }
+    }
+
+    impl ObjectImpl for Strobe {
+        fn constructed(&self) {
+            self.parent_constructed();
+        }
+    }
+
+    impl WidgetImpl for Strobe {}
+    impl BinImpl for Strobe {}
+}
+
+glib::wrapper! {
+    pub struct Strobe(ObjectSubclass<imp::Strobe>)
+        @extends gtk::Widget, adw::Bin;
+}
+
+impl Strobe {
+    pub fn new() -> Strobe {
+        let object: Strobe = glib::Object::builder::<Strobe>().build();
+        object.construct();
+        object
+    }
+
+    fn construct(&self) {
+        let imp = self.imp();
+
+        self.set_hexpand(true);
+        self.set_vexpand(true);
+        self.set_halign(gtk::Align::Fill);
+        self.set_valign(gtk::Align::Fill);
+
+        imp.drawing_area.set_hexpand(true);
+        imp.drawing_area.set_vexpand(true);
+        imp.drawing_area
+            .set_draw_func(clone!(@strong self as this => move |_, context, _, _| {
+                match this.draw(context) {
+                    Ok(_) => (),
+                    Err(e) => {
+                        error!("{}", e);
+                    },
+                }
+            }));
+
+        //advance the pattern once per frame, by the time elapsed since the last one
+        imp.drawing_area.add_tick_callback(
+            clone!(@weak self as this => @default-return glib::Continue(false), move |_, clock| {
+                this.tick(clock);
+                glib::Continue(true)
+            }),
+        );
+
+        self.set_child(Some(&imp.drawing_area));
+    }
+
+    //set the deviation from the target note, None when nothing is heard
+    pub fn set_deviation(&self, cents: Option<f64>) {
+        self.imp().cents.set(cents);
+    }
+
+    fn tick(&self, clock: &gdk::FrameClock) {
+        let imp = self.imp();
+        let frame_time = clock.frame_time(); //microseconds
+
+        let elapsed = match imp.last_frame.replace(Some(frame_time)) {
+            Some(last) => (frame_time - last) as f64 / 1_000_000.0,
+            None => 0.0,
+        };
+
+        if let Some(cents) = imp.cents.get() {
+            let speed = cents.clamp(-MAX_CENTS, MAX_CENTS) * DRIFT_PER_CENT;
+            imp.phase.set((imp.phase.get() + speed * elapsed).rem_euclid(1.0));
+        }
+
+        imp.drawing_area.queue_draw();
+    }
+
+    fn draw(&self, context: &cairo::Context) -> Result<(), Box<dyn Error>> {
+        let imp = self.imp();
+
+        //same palette as the gauge
+        let stripe_color = "#364da5";
+        let in_tune_color = "#ABE8FF";
+        let background_color = "#FFD2C4";
+
+        let width = self.width() as f64;
+        let height = self.height() as f64;
+
+        let margin = height * 0.1;
+        let band_height = (height - 2.0 * margin) / BANDS as f64;
+        let base_stripe = width / 8.0;
+
+        let color = util::hex_to_rgb(background_color);
+        context.set_source_rgba(color.0, color.1, color.2, 0.25);
+        context.rectangle(0.0, margin, width, height - 2.0 * margin);
+        context.fill()?;
+
+        let in_tune = matches!(imp.cents.get(), Some(cents) if cents.abs() < IN_TUNE_CENTS);
+        let color = match in_tune {
+            true => util::hex_to_rgb(in_tune_color),
+            false => util::hex_to_rgb(stripe_color),
+        };
+        let alpha = match imp.cents.get() {
+            Some(_) => 1.0,
+            None => 0.3,
+        };
+        context.set_source_rgba(color.0, color.1, color.2, alpha);
+
+        //every band drifts by the same number of its own periods, so the finer
+        //bands appear slower, like the octave bands of a mechanical strobe
+        for band in 0..BANDS {
+            let stripe = base_stripe / 2.0_f64.powi(band);
+            let top = margin + band as f64 * band_height;
+            let offset = imp.phase.get() * stripe - stripe;
+
+            let mut x = offset;
+            while x < width {
+                context.rectangle(x, top, stripe / 2.0, band_height);
+                x += stripe;
+            }
+        }
+
+        context.fill()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+/* synthetic_source.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+//built in test signals, for trying the tuner without a microphone, e.g. on a
+//headless machine with
+//  chromatic --cli --backend synthetic --device "Vibrato A3"
+//the signals are generated in real time and analysed like a live input
+
+use gtk::glib::Sender;
+
+use std::{error::Error, f64::consts::PI, thread};
+use std::sync::{atomic::{AtomicU64, Ordering}, mpsc, Arc};
+use std::time::{Duration, Instant};
+use log::{debug, error};
+
+use super::audio_source::{AudioSource, AudioSourceError, InputDevice};
+use super::i18n::i18n_k;
+use super::recorder::{Analyser, AnalysisSettings};
+use super::window::AudioAction;
+
+const SAMPLE_RATE: f64 = 44100.0;
+const AMPLITUDE: f64 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Tone {
+    Sine,
+    Sawtooth,
+    //strong upper partials over a weak fundamental, hard for some detectors
+    Harmonic,
+    Silent,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Signal {
+    name: &'static str,
+    tone: Tone,
+    frequency: f64,
+    //frequency swept up to and back down, over glide_seconds each way
+    glide_to: Option<f64>,
+    glide_seconds: f64,
+    vibrato_cents: f64,
+    vibrato_rate: f64,
+}
+
+const SIGNALS: [Signal; 6] = [
+    Signal { name: "Sine A4", tone: Tone::Sine, frequency: 440.0, glide_to: None, glide_seconds: 0.0, vibrato_cents: 0.0, vibrato_rate: 0.0 },
+    Signal { name: "Sawtooth E2", tone: Tone::Sawtooth, frequency: 82.41, glide_to: None, glide_seconds: 0.0, vibrato_cents: 0.0, vibrato_rate: 0.0 },
+    Signal { name: "Harmonic G3", tone: Tone::Harmonic, frequency: 196.0, glide_to: None, glide_seconds: 0.0, vibrato_cents: 0.0, vibrato_rate: 0.0 },
+    Signal { name: "Glide C3-C5", tone: Tone::Sine, frequency: 130.81, glide_to: Some(523.25), glide_seconds: 8.0, vibrato_cents: 0.0, vibrato_rate: 0.0 },
+    Signal { name: "Vibrato A3", tone: Tone::Sine, frequency: 220.0, glide_to: None, glide_seconds: 0.0, vibrato_cents: 30.0, vibrato_rate: 5.5 },
+    Signal { name: "Noise", tone: Tone::Silent, frequency: 0.0, glide_to: None, glide_seconds: 0.0, vibrato_cents: 0.0, vibrato_rate: 0.0 },
+];
+
+impl Signal {
+    fn frequency_at(&self, time: f64) -> f64 {
+        let mut frequency = match self.glide_to {
+            //sweep evenly in pitch, not in Hz
+            Some(to) => {
+                let sweep = (time / self.glide_seconds) % 2.0;
+                let fraction = if sweep < 1.0 { sweep } else { 2.0 - sweep };
+                self.frequency * (to / self.frequency).powf(fraction)
+            }
+            None => self.frequency,
+        };
+
+        if self.vibrato_cents > 0.0 {
+            let cents = self.vibrato_cents * (2.0 * PI * self.vibrato_rate * time).sin();
+            frequency *= 2.0_f64.powf(cents / 1200.0);
+        }
+
+        frequency
+    }
+
+    //value at phase, in periods
+    fn sample(&self, phase: f64) -> f64 {
+        match self.tone {
+            Tone::Sine => (2.0 * PI * phase).sin(),
+            Tone::Sawtooth => 2.0 * phase - 1.0,
+            Tone::Harmonic => {
+                let partials = [0.2, 1.0, 0.8, 0.6, 0.5, 0.4, 0.3, 0.2];
+                let sum: f64 = partials.iter().sum();
+                partials
+                    .iter()
+                    .enumerate()
+                    .map(|(i, a)| a * (2.0 * PI * (i + 1) as f64 * phase).sin())
+                    .sum::<f64>()
+                    / sum
+                    * 2.0
+            }
+            Tone::Silent => 0.0,
+        }
+    }
+}
+
+//xorshift, noise only has to sound like noise
+struct Noise(u64);
+
+impl Noise {
+    //uniform between -1 and 1, an rms of 1/sqrt(3)
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+}
+
+//signal to noise ratio in dB, shared with the running signal so changing it
+//does not have to restart the signal
+#[derive(Clone, Debug)]
+pub struct Snr(Arc<AtomicU64>);
+
+impl Snr {
+    pub fn new(snr: f64) -> Snr {
+        Snr(Arc::new(AtomicU64::new(snr.to_bits())))
+    }
+
+    pub fn set(&self, snr: f64) {
+        self.0.store(snr.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug)]
+pub struct SyntheticSource {
+    snr: Snr,
+    tx: Option<mpsc::Sender<()>>,
+}
+
+impl SyntheticSource {
+    pub fn new(snr: Snr) -> SyntheticSource {
+        SyntheticSource { snr, tx: None }
+    }
+}
+
+impl AudioSource for SyntheticSource {
+    fn devices(&self) -> Result<Vec<InputDevice>, Box<dyn Error>> {
+        Ok(SIGNALS.iter().map(|signal| InputDevice::named(signal.name)).collect())
+    }
+
+    fn start(&mut self, device: Option<&InputDevice>, analysis: AnalysisSettings, glib_sender: Sender<AudioAction>) -> Result<(), Box<dyn Error>> {
+        let signal = match device {
+            Some(device) => *SIGNALS
+                .iter()
+                .find(|signal| signal.name == device.id)
+                .ok_or_else(|| AudioSourceError(format!("no test signal {}", device.id)))?,
+            None => SIGNALS[0],
+        };
+
+        debug!("synthetic -> {:?} at {} dB snr", signal.name, self.snr.get());
+
+        let buffer_size = analysis.buffer_size as usize;
+        let snr = self.snr.clone();
+
+        //dropping the sender ends the previous thread
+        let (tx, rx) = mpsc::channel::<()>();
+        self.tx = Some(tx);
+
+        thread::spawn(move || {
+            let mut analyser = match Analyser::new(analysis, SAMPLE_RATE, glib_sender.clone()) {
+                Ok(analyser) => analyser,
+                Err(e) => {
+                    error!("synthetic -> unable to create analyser: {}", e);
+                    let message = i18n_k("Unable to analyse audio ({error})", &[("error", &e.to_string())]);
+                    if let Err(e) = glib_sender.send(AudioAction::Error(message)) {
+                        error!("{}", e);
+                    }
+                    return;
+                }
+            };
+            let mut noise = Noise(0x2545f4914f6cdd1d);
+            let mut buffer = vec![0.0_f32; buffer_size];
+            let mut phase = 0.0;
+            let mut sample_index: u64 = 0;
+            let start = Instant::now();
+
+            loop {
+                let time = sample_index as f64 / SAMPLE_RATE;
+
+                for sample in buffer.iter_mut() {
+                    let t = sample_index as f64 / SAMPLE_RATE;
+                    *sample = (signal.sample(phase) * AMPLITUDE) as f32;
+                    phase = (phase + signal.frequency_at(t) / SAMPLE_RATE).fract();
+                    sample_index += 1;
+                }
+
+                //the noise level follows the signal's, the noise signal has a fixed level
+                let rms = (buffer.iter().map(|s| (s * s) as f64).sum::<f64>() / buffer_size as f64).sqrt();
+                let noise_rms = match signal.tone {
+                    Tone::Silent => 0.1,
+                    _ => rms * 10.0_f64.powf(-snr.get() / 20.0),
+                };
+                for sample in buffer.iter_mut() {
+                    *sample += (noise.next() * noise_rms * 3.0_f64.sqrt()) as f32;
+                }
+
+                analyser.process(&buffer);
+
+                //keep to real time, like a sound card would
+                let due = Duration::from_secs_f64(time + buffer_size as f64 / SAMPLE_RATE);
+                if let Some(wait) = due.checked_sub(start.elapsed()) {
+                    thread::sleep(wait);
+                }
+
+                match rx.try_recv() {
+                    Ok(_) | Err(mpsc::TryRecvError::Disconnected) => break,
+                    Err(mpsc::TryRecvError::Empty) => (),
+                }
+            }
+
+            debug!("synthetic -> closing ...");
+        });
+
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.tx = None;
+    }
+}
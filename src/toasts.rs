@@ -1,24 +1,31 @@
 use gtk::prelude::*;
 use gtk::gio;
 
+use log::{debug, error};
+
 use super::i18n::i18n_k;
 use super::window::Window;
 
 pub static SUCCESS_GREEN: &str = "\"#57e389\"";
 pub static ERROR_RED: &str = "\"#c01c28\"";
 
+//None when running without a window, as in the terminal mode
+fn main_window() -> Option<Window> {
+    gio::Application::default()
+        .and_then(|app| app.downcast::<gtk::Application>().ok())
+        .and_then(|app| app.active_window())
+        .and_then(|win| win.downcast::<Window>().ok())
+}
+
 #[allow(dead_code)]
 pub fn add_toast_markup(msg: &str) {
-    let app = gio::Application::default()
-        .expect("Failed to retrieve application singleton")
-        .downcast::<gtk::Application>()
-        .unwrap();
-    
-    let win = app
-        .active_window()
-        .unwrap()
-        .downcast::<Window>()
-        .unwrap();
+    let win = match main_window() {
+        Some(win) => win,
+        None => {
+            debug!("{}", msg);
+            return;
+        }
+    };
 
         let toast = adw::Toast::new(msg);
         toast.set_timeout(1);
@@ -27,16 +34,13 @@ pub fn add_toast_markup(msg: &str) {
 
 #[allow(dead_code)]
 pub fn add_success_toast(verb: &str, msg: &str) {
-    let app = gio::Application::default()
-        .expect("Failed to retrieve application singleton")
-        .downcast::<gtk::Application>()
-        .unwrap();
-    
-    let win = app
-        .active_window()
-        .unwrap()
-        .downcast::<Window>()
-        .unwrap();
+    let win = match main_window() {
+        Some(win) => win,
+        None => {
+            debug!("{}", msg);
+            return;
+        }
+    };
 
         let toast = adw::Toast::new(format!("<span foreground={}>{}</span> {}", SUCCESS_GREEN, verb, msg).as_str());
         toast.set_timeout(1);
@@ -45,16 +49,13 @@ pub fn add_success_toast(verb: &str, msg: &str) {
 
 #[allow(dead_code)]
 pub fn add_error_toast(msg: String) {
-    let app = gio::Application::default()
-        .expect("Failed to retrieve application singleton")
-        .downcast::<gtk::Application>()
-        .unwrap();
-    
-    let win = app
-        .active_window()
-        .unwrap()
-        .downcast::<Window>()
-        .unwrap();
+    let win = match main_window() {
+        Some(win) => win,
+        None => {
+            error!("{}", msg);
+            return;
+        }
+    };
 
         // Translators: Only replace "Error!". Reorder if necessary
         let toast = adw::Toast::new(&i18n_k("<span foreground={ERROR_RED}>Error!</span> {error_msg}", &[("ERROR_RED", ERROR_RED), ("error_msg", &msg)]));
@@ -0,0 +1,214 @@
+/* tone_generator.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use std::{error::Error, f64::consts::PI, fmt, rc::Rc};
+use std::sync::{Arc, Mutex};
+use log::debug;
+
+use portaudio;
+
+//seconds a tone sounds for when not droning
+const TONE_SECONDS: f64 = 2.0;
+//fade in and out time, avoids clicks when starting or stopping
+const FADE_SECONDS: f64 = 0.02;
+const FRAMES: u32 = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Sine,
+    Triangle,
+    Sawtooth,
+    Organ,
+}
+
+impl Waveform {
+    //matches the values of the tone-waveform enum in the gschema
+    pub fn from_index(index: i32) -> Waveform {
+        match index {
+            1 => Waveform::Triangle,
+            2 => Waveform::Sawtooth,
+            3 => Waveform::Organ,
+            _ => Waveform::Sine,
+        }
+    }
+
+    //value at phase, in periods, between -1 and 1
+    fn sample(&self, phase: f64) -> f64 {
+        match self {
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            //a few octave and twelfth partials, like an organ flue pipe with its mixtures
+            Waveform::Organ => {
+                let partials = [(1.0, 1.0), (2.0, 0.5), (3.0, 0.3), (4.0, 0.2)];
+                partials.iter().map(|(h, a)| a * (2.0 * PI * h * phase).sin()).sum::<f64>() / 2.0
+            }
+        }
+    }
+}
+
+//equal tempered frequency of a midi note, tuned to the reference pitch of A4
+pub fn note_frequency(midi: i32, reference: f64) -> f64 {
+    reference * 2.0_f64.powf((midi - 69) as f64 / 12.0)
+}
+
+#[derive(Debug)]
+struct ToneState {
+    frequency: f64,
+    waveform: Waveform,
+    volume: f64,
+    //samples left before fading out, None for a drone
+    remaining: Option<usize>,
+    playing: bool,
+}
+
+type OutputStream = portaudio::Stream<portaudio::NonBlocking, portaudio::Output<f32>>;
+
+pub struct ToneGenerator {
+    pa: Rc<portaudio::PortAudio>,
+    state: Arc<Mutex<ToneState>>,
+    stream: Option<OutputStream>,
+    sample_rate: f64,
+}
+
+impl fmt::Debug for ToneGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ToneGenerator")
+            .field("state", &self.state)
+            .field("sample_rate", &self.sample_rate)
+            .finish()
+    }
+}
+
+impl ToneGenerator {
+    //pa is the instance owned by the recorder, a second one is not allowed
+    pub fn new(pa: Rc<portaudio::PortAudio>) -> ToneGenerator {
+        ToneGenerator {
+            pa,
+            state: Arc::new(Mutex::new(ToneState {
+                frequency: 440.0,
+                waveform: Waveform::Sine,
+                volume: 0.5,
+                remaining: None,
+                playing: false,
+            })),
+            stream: None,
+            sample_rate: 44100.0,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        let active = match self.stream.as_ref() {
+            Some(stream) => stream.is_active().unwrap_or(false),
+            None => false,
+        };
+        active && self.state.lock().unwrap().playing
+    }
+
+    //start playing, or retune the tone already playing
+    pub fn play(&mut self, frequency: f64, waveform: Waveform, volume: f64, drone: bool) -> Result<(), Box<dyn Error>> {
+        if self.stream.as_ref().map(|s| !s.is_active().unwrap_or(false)).unwrap_or(true) {
+            self.open_stream()?;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.frequency = frequency;
+        state.waveform = waveform;
+        state.volume = volume;
+        state.remaining = match drone {
+            true => None,
+            false => Some((TONE_SECONDS * self.sample_rate) as usize),
+        };
+        state.playing = true;
+
+        debug!("tone generator -> {} Hz {:?}", frequency, waveform);
+        Ok(())
+    }
+
+    //fade out, the stream completes once silent
+    pub fn stop(&self) {
+        self.state.lock().unwrap().playing = false;
+    }
+
+    //change the volume or waveform without restarting the tone
+    pub fn update(&self, waveform: Waveform, volume: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.waveform = waveform;
+        state.volume = volume;
+    }
+
+    fn open_stream(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(mut stream) = self.stream.take() {
+            stream.close()?;
+        }
+
+        let device = self.pa.default_output_device()?;
+        self.sample_rate = self.pa.device_info(device)?.default_sample_rate;
+        let settings = self.pa.default_output_stream_settings::<f32>(1, self.sample_rate, FRAMES)?;
+
+        let sample_rate = self.sample_rate;
+        let fade_step = 1.0 / (FADE_SECONDS * sample_rate);
+        let state = self.state.clone();
+        let mut phase = 0.0;
+        let mut gain = 0.0;
+
+        let callback = move |portaudio::OutputStreamCallbackArgs { buffer, .. }| {
+            let mut guard = state.lock().unwrap();
+            let state = &mut *guard;
+
+            for sample in buffer.iter_mut() {
+                if let Some(remaining) = state.remaining.as_mut() {
+                    match *remaining {
+                        0 => state.playing = false,
+                        _ => *remaining -= 1,
+                    }
+                }
+
+                let target = if state.playing { state.volume } else { 0.0 };
+                if gain < target {
+                    gain = (gain + fade_step).min(target);
+                } else if gain > target {
+                    gain = (gain - fade_step).max(target);
+                }
+
+                *sample = (state.waveform.sample(phase) * gain) as f32;
+                phase = (phase + state.frequency / sample_rate).fract();
+            }
+
+            match !state.playing && gain <= 0.0 {
+                true => portaudio::Complete,
+                false => portaudio::Continue,
+            }
+        };
+
+        let mut stream = self.pa.open_non_blocking_stream(settings, callback)?;
+        stream.start()?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+}
+
+impl Drop for ToneGenerator {
+    fn drop(&mut self) {
+        if let Some(mut stream) = self.stream.take() {
+            let _ = stream.close();
+        }
+    }
+}
@@ -0,0 +1,479 @@
+/* tuning.rs
+ *
+ * Copyright 2023 nate-xyz
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * SPDX-License-Identifier: GPL-3.0-or-later
+ */
+
+use gtk::gio;
+use gtk::prelude::*;
+
+use std::error::Error;
+
+use super::scala::{KeyboardMapping, Scale};
+
+pub static NOTE_NAMES: [&str; 12] = ["C", "C♯", "D", "D♯", "E", "F", "F♯", "G", "G♯", "A", "A♯", "B"];
+
+//semitones written pitch sits above concert pitch, in the order of the
+//transposition presets in the preferences window
+pub static TRANSPOSITIONS: [i32; 11] = [0, 2, 3, 9, 14, 21, 7, -3, 5, 12, -12];
+
+//midi number of A4, the note the reference pitch is given for
+const REFERENCE_STEP: f64 = 69.0;
+
+#[derive(Clone, Debug)]
+pub struct Note {
+    pub name: String,
+    pub octave: i32,
+    pub frequency: f64, //target frequency of the note
+    pub cents: f64, //deviation of the detected frequency from the target
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Temperament {
+    Equal,
+    Pythagorean,
+    QuarterCommaMeantone,
+    WerckmeisterIII,
+    Vallotti,
+    KirnbergerIII,
+    JustIntonation,
+}
+
+impl Temperament {
+    //matches the values of the temperament enum in the gschema
+    pub fn from_index(index: i32) -> Temperament {
+        match index {
+            1 => Temperament::Pythagorean,
+            2 => Temperament::QuarterCommaMeantone,
+            3 => Temperament::WerckmeisterIII,
+            4 => Temperament::Vallotti,
+            5 => Temperament::KirnbergerIII,
+            6 => Temperament::JustIntonation,
+            _ => Temperament::Equal,
+        }
+    }
+
+    //deviation in cents of each degree above the tonic from its equal tempered position
+    pub fn offsets(&self) -> [f64; 12] {
+        match self {
+            Temperament::Equal => [0.0; 12],
+            //chain of pure fifths from E♭ to G♯
+            Temperament::Pythagorean => [
+                0.0, 13.685, 3.910, -5.865, 7.820, -1.955, 11.730, 1.955, 15.640, 5.865, -3.910, 9.775,
+            ],
+            //fifths narrowed by a quarter syntonic comma from E♭ to G♯
+            Temperament::QuarterCommaMeantone => [
+                0.0, -23.951, -6.843, 10.265, -13.686, 3.422, -20.529, -3.422, -27.373, -10.265, 6.843, -17.108,
+            ],
+            //C-G-D-A and B-F♯ narrowed by a quarter pythagorean comma
+            Temperament::WerckmeisterIII => [
+                0.0, -9.775, -7.820, -5.865, -9.775, -1.955, -11.730, -3.910, -7.820, -11.730, -3.910, -7.820,
+            ],
+            //F-C-G-D-A-E-B narrowed by a sixth pythagorean comma
+            Temperament::Vallotti => [
+                0.0, -5.865, -3.910, -1.955, -7.820, 1.955, -7.820, -1.955, -3.910, -5.865, 0.0, -9.775,
+            ],
+            //C-G-D-A-E narrowed by a quarter syntonic comma, F♯-C♯ by a schisma
+            Temperament::KirnbergerIII => [
+                0.0, -9.775, -6.843, -5.865, -13.686, -1.955, -9.776, -3.422, -7.820, -10.265, -3.910, -11.731,
+            ],
+            //5-limit ratios 1/1 16/15 9/8 6/5 5/4 4/3 45/32 3/2 8/5 5/3 16/9 15/8
+            Temperament::JustIntonation => [
+                0.0, 11.731, 3.910, 15.641, -13.686, -1.955, -9.776, 1.955, 13.686, -15.641, -3.910, -11.731,
+            ],
+        }
+    }
+}
+
+//find the tempered note closest to frequency, named at written pitch, transposition
+//semitones above concert pitch. the temperament is built on the tonic and shifted
+//as a whole so that A4 keeps the reference pitch
+pub fn nearest_note(frequency: f64, reference: f64, temperament: Temperament, tonic: i32, transposition: i32) -> Note {
+    let offsets = temperament.offsets();
+    //deviation of the tempered A from the equal tempered one
+    let anchor = offsets[(REFERENCE_STEP as i32 - tonic).rem_euclid(12) as usize];
+
+    //written position of the frequency in equal tempered semitones
+    let position = REFERENCE_STEP + transposition as f64 + 12.0 * (frequency / reference).log2();
+    let rounded = position.round() as i32;
+
+    //tempered offsets are below a semitone, so the closest note is one of the neighbours
+    let (step, target) = (rounded - 1..rounded + 2)
+        .map(|candidate| {
+            //the temperament is laid out at concert pitch
+            let degree = (candidate - transposition - tonic).rem_euclid(12) as usize;
+            (candidate, candidate as f64 + (offsets[degree] - anchor) / 100.0)
+        })
+        .min_by(|a, b| (position - a.1).abs().total_cmp(&(position - b.1).abs()))
+        .unwrap();
+
+    Note {
+        name: NOTE_NAMES[step.rem_euclid(12) as usize].to_string(),
+        octave: step.div_euclid(12) - 1,
+        frequency: reference * 2.0_f64.powf((target - transposition as f64 - REFERENCE_STEP) / 12.0),
+        cents: 100.0 * (position - target),
+    }
+}
+
+//step sizes of an equal division of the octave, used to spell its steps with
+//ups and downs notation: naturals are placed on the chain of fifths closest to
+//3/2, sharps and flats move by a chroma and the remainder is filled with ups (^)
+//and downs (v), or half sharps and flats when they split a chroma evenly
+struct EdoSpelling {
+    divisions: i32,
+    naturals: [i32; 7],
+    chroma: i32,
+}
+
+static NATURAL_NAMES: [&str; 7] = ["C", "D", "E", "F", "G", "A", "B"];
+
+impl EdoSpelling {
+    fn new(divisions: i32) -> EdoSpelling {
+        let fifth = (divisions as f64 * 1.5_f64.log2()).round() as i32;
+        let tone = 2 * fifth - divisions;
+        let limma = 3 * divisions - 5 * fifth;
+
+        EdoSpelling {
+            divisions,
+            naturals: [0, tone, 2 * tone, 2 * tone + limma, 3 * tone + limma, 4 * tone + limma, 5 * tone + limma],
+            chroma: tone - limma,
+        }
+    }
+
+    //name of a step and the octave shift for spellings that cross C, like B♯ or C♭
+    fn name(&self, step: i32) -> (String, i32) {
+        let step = step.rem_euclid(self.divisions);
+
+        //spell from the closest natural, then with the fewest ups and the fewest
+        //sharps, sharps before flats
+        let wrap = |steps: i32| (steps + self.divisions / 2).rem_euclid(self.divisions) - self.divisions / 2;
+        let (_, natural, sharps, ups) = self
+            .naturals
+            .iter()
+            .enumerate()
+            .flat_map(|(natural, position)| {
+                [1, -1, 2, -2, 0].into_iter().map(move |sharps| {
+                    let distance = wrap(step - position).abs();
+                    (distance, natural, sharps, wrap(step - position - sharps * self.chroma))
+                })
+            })
+            .min_by_key(|(distance, _, sharps, ups)| (*distance, ups.abs(), sharps.abs(), *sharps < 0))
+            .unwrap();
+
+        let shift = match (natural, step > self.divisions / 2) {
+            (0, true) => 1,
+            (6, false) => -1,
+            _ => 0,
+        };
+
+        if sharps == 0 && ups != 0 && 2 * ups.abs() == self.chroma {
+            let half = if ups > 0 { "𝄲" } else { "𝄳" };
+            return (format!("{}{}", NATURAL_NAMES[natural], half), shift);
+        }
+
+        let accidental = match sharps {
+            2 => "𝄪",
+            1 => "♯",
+            -1 => "♭",
+            -2 => "𝄫",
+            _ => "",
+        };
+        let arrows = match ups > 0 {
+            true => "^".repeat(ups as usize),
+            false => "v".repeat(ups.unsigned_abs() as usize),
+        };
+
+        (format!("{}{}{}", arrows, NATURAL_NAMES[natural], accidental), shift)
+    }
+}
+
+//find the closest step of an equal division of the octave, with A4 on the reference
+pub fn nearest_edo_note(frequency: f64, reference: f64, divisions: i32) -> Note {
+    let spelling = EdoSpelling::new(divisions);
+
+    //position in steps above C4
+    let position = spelling.naturals[5] as f64 + divisions as f64 * (frequency / reference).log2();
+    let step = position.round() as i32;
+    let (name, shift) = spelling.name(step);
+
+    Note {
+        name,
+        octave: 4 + step.div_euclid(divisions) + shift,
+        frequency: reference * 2.0_f64.powf((step - spelling.naturals[5]) as f64 / divisions as f64),
+        cents: 1200.0 * (position - step as f64) / divisions as f64,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum TuningSystem {
+    Temperament(Temperament, i32),
+    Scala(Scale, Option<KeyboardMapping>),
+    Edo(i32),
+}
+
+impl TuningSystem {
+    //the temperament chosen in the settings, used on its own or as the fallback
+    pub fn temperament(settings: &gio::Settings) -> TuningSystem {
+        TuningSystem::Temperament(
+            Temperament::from_index(settings.enum_("temperament")),
+            settings.int("temperament-tonic"),
+        )
+    }
+
+    //the tuning system chosen in tuning-system. a scala tuning without a scale
+    //file falls back to the temperament, an unreadable one is an error
+    pub fn from_settings(settings: &gio::Settings) -> Result<TuningSystem, Box<dyn Error>> {
+        match settings.enum_("tuning-system") {
+            1 => {
+                let scale_path = settings.string("scala-file").to_string();
+                let mapping_path = settings.string("kbm-file").to_string();
+
+                if scale_path.is_empty() {
+                    return Ok(Self::temperament(settings));
+                }
+
+                let scale = Scale::load(&scale_path)?;
+                let mapping = match mapping_path.is_empty() {
+                    true => None,
+                    false => Some(KeyboardMapping::load(&mapping_path)?),
+                };
+
+                Ok(TuningSystem::Scala(scale, mapping))
+            }
+            2 => Ok(TuningSystem::Edo(settings.int("edo-divisions"))),
+            _ => Ok(Self::temperament(settings)),
+        }
+    }
+
+    //the note is named at written pitch, transposition semitones above concert pitch,
+    //by finding it in a transposed frame. its target frequency stays at concert pitch
+    pub fn nearest_note(&self, frequency: f64, reference: f64, transposition: i32) -> Note {
+        let (shift, mut note) = match self {
+            //the temperament stays laid out at concert pitch
+            TuningSystem::Temperament(temperament, tonic) => {
+                (0.0, nearest_note(frequency, reference, *temperament, *tonic, transposition))
+            }
+            //scala degrees have no written pitch to transpose to
+            TuningSystem::Scala(scale, mapping) => (0.0, scale.nearest_note(mapping.as_ref(), frequency, reference)),
+            //move by whole steps so the deviation from the step is kept
+            TuningSystem::Edo(divisions) => {
+                let shift = (transposition as f64 * *divisions as f64 / 12.0).round() / *divisions as f64;
+                (shift, nearest_edo_note(frequency * 2.0_f64.powf(shift), reference, *divisions))
+            }
+        };
+
+        note.frequency /= 2.0_f64.powf(shift);
+        note
+    }
+
+    //average distance in cents between neighbouring notes
+    pub fn step_size(&self) -> f64 {
+        match self {
+            TuningSystem::Temperament(_, _) => 100.0,
+            TuningSystem::Scala(scale, _) => scale.period() / scale.len() as f64,
+            TuningSystem::Edo(divisions) => 1200.0 / *divisions as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPERAMENTS: [Temperament; 7] = [
+        Temperament::Equal,
+        Temperament::Pythagorean,
+        Temperament::QuarterCommaMeantone,
+        Temperament::WerckmeisterIII,
+        Temperament::Vallotti,
+        Temperament::KirnbergerIII,
+        Temperament::JustIntonation,
+    ];
+
+    fn cents(ratio: f64) -> f64 {
+        1200.0 * ratio.log2()
+    }
+
+    //offsets of a chain of fifths of the given size, from E♭ to G♯
+    fn chain_offsets(fifth: f64) -> [f64; 12] {
+        let mut offsets = [0.0; 12];
+        for k in -3..9_i32 {
+            let degree = (7 * k).rem_euclid(12) as usize;
+            let pitch = (fifth * k as f64).rem_euclid(1200.0);
+            offsets[degree] = pitch - 100.0 * degree as f64;
+        }
+        offsets
+    }
+
+    fn assert_offsets(actual: [f64; 12], expected: [f64; 12]) {
+        for (degree, (a, e)) in actual.iter().zip(expected.iter()).enumerate() {
+            assert!((a - e).abs() < 2e-3, "degree {}: {} != {}", degree, a, e);
+        }
+    }
+
+    #[test]
+    fn test_offsets() {
+        for (index, temperament) in TEMPERAMENTS.iter().enumerate() {
+            assert_eq!(Temperament::from_index(index as i32), *temperament);
+            //the tonic keeps its equal tempered pitch
+            assert_eq!(temperament.offsets()[0], 0.0);
+            //every degree stays closer to its own equal tempered step than to a neighbour
+            assert!(temperament.offsets().iter().all(|offset| offset.abs() < 50.0));
+        }
+
+        assert_offsets(Temperament::Equal.offsets(), [0.0; 12]);
+        assert_offsets(Temperament::Pythagorean.offsets(), chain_offsets(cents(1.5)));
+        //four quarter comma meantone fifths make two octaves and a pure major third
+        assert_offsets(Temperament::QuarterCommaMeantone.offsets(), chain_offsets(cents(5.0) / 4.0));
+
+        let just = [1.0, 16.0 / 15.0, 9.0 / 8.0, 6.0 / 5.0, 5.0 / 4.0, 4.0 / 3.0, 45.0 / 32.0, 3.0 / 2.0, 8.0 / 5.0, 5.0 / 3.0, 16.0 / 9.0, 15.0 / 8.0];
+        let mut expected = [0.0; 12];
+        for (degree, ratio) in just.iter().enumerate() {
+            expected[degree] = cents(*ratio) - 100.0 * degree as f64;
+        }
+        assert_offsets(Temperament::JustIntonation.offsets(), expected);
+    }
+
+    #[test]
+    fn test_nearest_note_equal() {
+        let note = nearest_note(440.0, 440.0, Temperament::Equal, 0, 0);
+        assert_eq!((note.name.as_str(), note.octave), ("A", 4));
+        assert!(note.cents.abs() < 1e-9);
+        assert!((note.frequency - 440.0).abs() < 1e-9);
+
+        let note = nearest_note(261.6256, 440.0, Temperament::Equal, 0, 0);
+        assert_eq!((note.name.as_str(), note.octave), ("C", 4));
+        assert!(note.cents.abs() < 1e-3);
+
+        //a sharp A, and the reference pitch moving the whole scale
+        let note = nearest_note(445.0, 440.0, Temperament::Equal, 0, 0);
+        assert_eq!((note.name.as_str(), note.octave), ("A", 4));
+        assert!((note.cents - cents(445.0 / 440.0)).abs() < 1e-9);
+
+        let note = nearest_note(442.0, 442.0, Temperament::Equal, 0, 0);
+        assert!(note.cents.abs() < 1e-9);
+
+        //just below the middle of B3 and C4 is still B3, the octave changes at C
+        let note = nearest_note(254.0, 440.0, Temperament::Equal, 0, 0);
+        assert_eq!((note.name.as_str(), note.octave), ("B", 3));
+        assert!(note.cents > 45.0 && note.cents < 50.0);
+    }
+
+    #[test]
+    fn test_nearest_note_tonic() {
+        //A4 is the reference whatever the temperament is built on
+        for index in 0..7 {
+            for tonic in 0..12 {
+                let note = nearest_note(440.0, 440.0, Temperament::from_index(index), tonic, 0);
+                assert_eq!((note.name.as_str(), note.octave), ("A", 4));
+                assert!(note.cents.abs() < 1e-9);
+                assert!((note.frequency - 440.0).abs() < 1e-9);
+            }
+        }
+
+        //the pythagorean C below it is three pure fifths down, 5.865 cents under the equal one
+        let c4 = 440.0 * 2.0_f64.powf(-9.0 / 12.0);
+        let note = nearest_note(c4, 440.0, Temperament::Pythagorean, 0, 0);
+        assert_eq!((note.name.as_str(), note.octave), ("C", 4));
+        assert!((note.cents - 5.865).abs() < 1e-3);
+
+        //a pure major third above the just C4, a pure major sixth below A4
+        let note = nearest_note(264.0 * 1.25, 440.0, Temperament::JustIntonation, 0, 0);
+        assert_eq!((note.name.as_str(), note.octave), ("E", 4));
+        assert!(note.cents.abs() < 1e-3);
+        assert!((note.frequency - 330.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_nearest_note_transposed() {
+        //concert A on a B♭ instrument is a written B, still at the reference
+        let note = nearest_note(440.0, 440.0, Temperament::Pythagorean, 0, 2);
+        assert_eq!((note.name.as_str(), note.octave), ("B", 4));
+        assert!(note.cents.abs() < 1e-9);
+        assert!((note.frequency - 440.0).abs() < 1e-9);
+
+        //and the tempered concert C a written D
+        let c4 = 440.0 * 2.0_f64.powf(-9.0 / 12.0);
+        let note = nearest_note(c4, 440.0, Temperament::Pythagorean, 0, 2);
+        assert_eq!((note.name.as_str(), note.octave), ("D", 4));
+        assert!((note.cents - 5.865).abs() < 1e-3);
+    }
+
+    fn edo_names(divisions: i32) -> Vec<String> {
+        let spelling = EdoSpelling::new(divisions);
+        (0..divisions).map(|step| spelling.name(step).0).collect()
+    }
+
+    #[test]
+    fn test_edo_12() {
+        assert_eq!(edo_names(12), NOTE_NAMES);
+
+        let note = nearest_edo_note(440.0, 440.0, 12);
+        assert_eq!((note.name.as_str(), note.octave), ("A", 4));
+        assert!(note.cents.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_edo_19() {
+        assert_eq!(
+            edo_names(19),
+            ["C", "C♯", "D♭", "D", "D♯", "E♭", "E", "E♯", "F", "F♯", "G♭", "G", "G♯", "A♭", "A", "A♯", "B♭", "B", "B♯"]
+        );
+
+        //B♯ is its own step below C
+        let spelling = EdoSpelling::new(19);
+        assert_eq!(spelling.name(18), ("B♯".to_string(), 0));
+
+        let note = nearest_edo_note(440.0 * 2.0_f64.powf(1.0 / 19.0), 440.0, 19);
+        assert_eq!((note.name.as_str(), note.octave), ("A♯", 4));
+        assert!(note.cents.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_edo_24() {
+        let names = edo_names(24);
+        assert_eq!(&names[..6], ["C", "C𝄲", "C♯", "D𝄳", "D", "D𝄲"]);
+        assert_eq!(names[19], "A𝄲");
+
+        //the quarter tone below C belongs to the next octave
+        let spelling = EdoSpelling::new(24);
+        assert_eq!(spelling.name(23), ("C𝄳".to_string(), 1));
+
+        let note = nearest_edo_note(440.0 * 2.0_f64.powf(5.0 / 24.0), 440.0, 24);
+        assert_eq!((note.name.as_str(), note.octave), ("C𝄳", 5));
+
+        //a quarter tone step is 50 cents wide
+        let note = nearest_edo_note(440.0 * 2.0_f64.powf(20.0 / 1200.0), 440.0, 24);
+        assert_eq!(note.name, "A");
+        assert!((note.cents - 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_edo_53() {
+        let spelling = EdoSpelling::new(53);
+        assert_eq!(spelling.chroma, 5);
+        assert_eq!(spelling.naturals, [0, 9, 18, 22, 31, 40, 49]);
+
+        let names = edo_names(53);
+        assert_eq!(&names[..5], ["C", "^C", "^^C", "vvC♯", "vC♯"]);
+        assert_eq!(names[22], "F");
+        assert_eq!(names[51], "vvC");
+
+        let note = nearest_edo_note(440.0 * 2.0_f64.powf(1.0 / 53.0), 440.0, 53);
+        assert_eq!((note.name.as_str(), note.octave), ("^A", 4));
+    }
+}
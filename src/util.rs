@@ -2,16 +2,45 @@ use gtk::prelude::*;
 use gtk::gio;
 
 use std::{cell::RefCell, rc::Rc};
+use log::error;
 
 use super::window::Window;
 use super::recorder::Recorder;
 use super::gauge::Gauge;
 
+const APP_ID: &str = "io.github.nate_xyz.Chromatic";
+
+thread_local! {
+    //settings handed out instead of the stored ones, see override_settings
+    static SETTINGS_OVERRIDE: RefCell<Option<gio::Settings>> = RefCell::new(None);
+}
+
 pub fn settings_manager() -> gio::Settings {
+    if let Some(settings) = SETTINGS_OVERRIDE.with(|s| s.borrow().clone()) {
+        return settings;
+    }
+
     // // We ship a single schema for both default and development profiles
     // let app_id = APPLICATION_ID.trim_end_matches(".Devel");
-    let app_id = "io.github.nate_xyz.Chromatic";
-    gio::Settings::new(app_id)
+    gio::Settings::new(APP_ID)
+}
+
+//from now on hand out an in memory copy of the stored settings, changes made
+//to it are never saved. used by the terminal mode for command line overrides
+pub fn override_settings() -> gio::Settings {
+    let stored = gio::Settings::new(APP_ID);
+    let settings = gio::Settings::with_backend(APP_ID, &gio::memory_settings_backend_new());
+
+    if let Some(schema) = stored.settings_schema() {
+        for key in schema.list_keys() {
+            if let Err(e) = settings.set_value(&key, &stored.value(&key)) {
+                error!("unable to copy setting {}: {}", key, e);
+            }
+        }
+    }
+
+    SETTINGS_OVERRIDE.with(|s| s.replace(Some(settings.clone())));
+    settings
 }
 
 
@@ -49,4 +78,17 @@ pub fn gauge() -> Rc<RefCell<Option<Gauge>>> {
         .downcast::<Window>()
         .unwrap()
         .gauge()
-}
\ No newline at end of file
+}
+
+//a colour given as #rrggbb, as cairo wants it
+pub fn hex_to_rgb(hex_string: &str) -> (f64, f64, f64) {
+    let r = u8::from_str_radix(&hex_string[1..3], 16).unwrap() as f64 / 255.0;
+    let g = u8::from_str_radix(&hex_string[3..5], 16).unwrap() as f64 / 255.0;
+    let b = u8::from_str_radix(&hex_string[5..7], 16).unwrap() as f64 / 255.0;
+    (r, g, b)
+}
+
+//map a level in dBFS onto 0..1 for a level bar, showing the top 60 dB
+pub fn level_fraction(db: f32) -> f64 {
+    ((db as f64 + 60.0) / 60.0).clamp(0.0, 1.0)
+}
@@ -22,24 +22,40 @@ use adw::prelude::*;
 use adw::subclass::prelude::*;
 use gtk::{gio, gio::SettingsBindFlags, glib, glib::clone, glib::Receiver};
 
-use std::{cell::{Cell, RefCell}, rc::Rc};
+use std::{cell::{Cell, RefCell}, path::Path, rc::Rc};
 use std::time::{Duration, Instant};
-use log::debug;
+use log::{debug, error};
 
-use pitch_calc::{Hz, LetterOctave};
-
-use super::i18n::i18n;
+use super::i18n::{i18n, i18n_k};
 use super::recorder::Recorder;
 use super::gauge::Gauge;
+use super::strobe::Strobe;
+use super::spectrum::Spectrum;
+use super::oscilloscope::Oscilloscope;
+use super::tone_generator::{self, ToneGenerator, Waveform};
+use super::tuning::NOTE_NAMES;
+use super::history::{PitchHistory, PitchSample};
+use super::file_source::FileSource;
+use super::recorder::AnalysisSettings;
 use super::util;
 use super::toasts;
+use super::tuning::{Note, Temperament, TuningSystem};
+use super::pitch_log::{LogFormat, PitchLog};
+use super::application::App;
+use super::osc::OscSender;
+use super::melody::{self, MelodyNote, MelodyRecorder};
+use super::instruments::{self, InstrumentPreset};
 
 
 
 #[derive(Clone, Debug)]
 pub enum AudioAction {
-    RawAudio(Vec<f32>),
-    Pitch(f32),
+    RawAudio(Vec<f32>, f64), //samples, sample rate
+    Pitch(f32, f32, f64), //frequency, confidence, seconds of audio the frame covers
+    Level(f32, f32), //rms, peak in dBFS
+    FilePosition(f64), //seconds into the file being analysed
+    FileFinished,
+    Error(String), //a failure on an audio thread, shown as a toast
 }
 
 mod imp {
@@ -63,6 +79,63 @@ mod imp {
         #[template_child(id = "cents_label")]
         pub cents_label: TemplateChild<gtk::Label>,
 
+        #[template_child(id = "strings_box")]
+        pub strings_box: TemplateChild<gtk::Box>,
+
+        #[template_child(id = "level_bar")]
+        pub level_bar: TemplateChild<gtk::LevelBar>,
+
+        #[template_child(id = "tone_note_dropdown")]
+        pub tone_note_dropdown: TemplateChild<gtk::DropDown>,
+
+        #[template_child(id = "tone_octave_adj")]
+        pub tone_octave_adj: TemplateChild<gtk::Adjustment>,
+
+        #[template_child(id = "tone_waveform_dropdown")]
+        pub tone_waveform_dropdown: TemplateChild<gtk::DropDown>,
+
+        #[template_child(id = "tone_volume_adj")]
+        pub tone_volume_adj: TemplateChild<gtk::Adjustment>,
+
+        #[template_child(id = "tone_drone_switch")]
+        pub tone_drone_switch: TemplateChild<gtk::Switch>,
+
+        #[template_child(id = "tone_play_button")]
+        pub tone_play_button: TemplateChild<gtk::Button>,
+
+        #[template_child(id = "tone_stop_button")]
+        pub tone_stop_button: TemplateChild<gtk::Button>,
+
+        #[template_child(id = "file_revealer")]
+        pub file_revealer: TemplateChild<gtk::Revealer>,
+
+        #[template_child(id = "file_play_button")]
+        pub file_play_button: TemplateChild<gtk::ToggleButton>,
+
+        #[template_child(id = "file_label")]
+        pub file_label: TemplateChild<gtk::Label>,
+
+        #[template_child(id = "file_scale")]
+        pub file_scale: TemplateChild<gtk::Scale>,
+
+        #[template_child(id = "file_position_adj")]
+        pub file_position_adj: TemplateChild<gtk::Adjustment>,
+
+        #[template_child(id = "file_realtime_button")]
+        pub file_realtime_button: TemplateChild<gtk::ToggleButton>,
+
+        #[template_child(id = "file_close_button")]
+        pub file_close_button: TemplateChild<gtk::Button>,
+
+        #[template_child(id = "log_button")]
+        pub log_button: TemplateChild<gtk::ToggleButton>,
+
+        #[template_child(id = "melody_button")]
+        pub melody_button: TemplateChild<gtk::ToggleButton>,
+
+        #[template_child(id = "history_bin")]
+        pub history_bin: TemplateChild<adw::Bin>,
+
         #[template_child(id = "gauge_bin")]
         pub gauge_bin: TemplateChild<adw::Bin>,
 
@@ -74,13 +147,31 @@ mod imp {
         pub note_box: TemplateChild<gtk::Box>,
 
         pub gauge: Rc<RefCell<Option<Gauge>>>,
+        pub strobe: RefCell<Option<Strobe>>,
+        pub display_stack: gtk::Stack,
+        pub history: PitchHistory,
+        pub spectrum: Spectrum,
+        pub oscilloscope: Oscilloscope,
+        pub tone_generator: RefCell<Option<ToneGenerator>>,
+        pub file_source: RefCell<Option<FileSource>>,
+        pub file_chooser: RefCell<Option<gtk::FileChooserNative>>,
+        pub pitch_log: RefCell<Option<PitchLog>>,
+        pub osc: RefCell<Option<OscSender>>,
+        pub melody: RefCell<Option<MelodyRecorder>>,
         pub base_pitch: Cell<f64>,
+        pub transposition: Cell<i32>,
+        pub minimum_confidence: Cell<f32>,
+        pub tuning: RefCell<TuningSystem>,
+        pub instrument: RefCell<Option<InstrumentPreset>>,
+        pub string_labels: RefCell<Vec<gtk::Label>>,
         pub frequency: Cell<f64>,
-        pub cents: Cell<i32>,
+        pub cents: Cell<f64>,
+        pub show_decimals: Cell<bool>,
         pub recorder: Rc<Recorder>,
         pub receiver: RefCell<Option<Receiver<AudioAction>>>,
         pub settings: gio::Settings,
         pub show_gauge: Cell<bool>,
+        pub device_restart_pending: Cell<bool>,
 
         pub hang_duration: Cell<u64>,
         pub hang_time: RefCell<Option<std::time::Instant>>
@@ -109,17 +200,54 @@ mod imp {
                 note_label: TemplateChild::default(),
                 frequency_label: TemplateChild::default(),
                 cents_label: TemplateChild::default(),
+                strings_box: TemplateChild::default(),
+                level_bar: TemplateChild::default(),
+                tone_note_dropdown: TemplateChild::default(),
+                tone_octave_adj: TemplateChild::default(),
+                tone_waveform_dropdown: TemplateChild::default(),
+                tone_volume_adj: TemplateChild::default(),
+                tone_drone_switch: TemplateChild::default(),
+                tone_play_button: TemplateChild::default(),
+                tone_stop_button: TemplateChild::default(),
+                file_revealer: TemplateChild::default(),
+                file_play_button: TemplateChild::default(),
+                file_label: TemplateChild::default(),
+                file_scale: TemplateChild::default(),
+                file_position_adj: TemplateChild::default(),
+                file_realtime_button: TemplateChild::default(),
+                file_close_button: TemplateChild::default(),
+                log_button: TemplateChild::default(),
+                melody_button: TemplateChild::default(),
+                history_bin: TemplateChild::default(),
                 gauge_bin: TemplateChild::default(),
                 gauge_box: TemplateChild::default(),
                 note_box: TemplateChild::default(),
                 gauge: Rc::new(RefCell::new(None)),
+                strobe: RefCell::new(None),
+                display_stack: gtk::Stack::new(),
+                history: PitchHistory::new(),
+                spectrum: Spectrum::new(),
+                oscilloscope: Oscilloscope::new(),
+                tone_generator: RefCell::new(None),
+                file_source: RefCell::new(None),
+                file_chooser: RefCell::new(None),
+                pitch_log: RefCell::new(None),
+                osc: RefCell::new(None),
+                melody: RefCell::new(None),
                 base_pitch: Cell::new(440.0),
+                transposition: Cell::new(0),
+                minimum_confidence: Cell::new(0.0),
+                tuning: RefCell::new(TuningSystem::Temperament(Temperament::Equal, 0)),
+                instrument: RefCell::new(None),
+                string_labels: RefCell::new(Vec::new()),
                 frequency: Cell::new(0.0),
-                cents: Cell::new(0),
+                cents: Cell::new(0.0),
+                show_decimals: Cell::new(false),
                 recorder: Rc::new(Recorder::new(sender)),
                 receiver: RefCell::new(Some(r)),
                 settings: util::settings_manager(),
                 show_gauge: Cell::new(true),
+                device_restart_pending: Cell::new(false),
                 hang_duration: Cell::new(3),
                 hang_time: RefCell::new(None),
             }
@@ -149,7 +277,23 @@ impl Window {
     fn setup(&self) {
         let imp = self.imp();
         let gauge = Gauge::new(300, 500);
-        imp.gauge_bin.set_child(Some(&gauge));
+        let strobe = Strobe::new();
+
+        //the displays share the space below the note, one shown at a time
+        imp.display_stack.add_named(&gauge, Some("gauge"));
+        imp.display_stack.add_named(&strobe, Some("strobe"));
+        imp.display_stack.add_named(&imp.spectrum, Some("spectrum"));
+        imp.display_stack.add_named(&imp.oscilloscope, Some("oscilloscope"));
+        imp.display_stack.set_transition_type(gtk::StackTransitionType::Crossfade);
+        imp.gauge_bin.set_child(Some(&imp.display_stack));
+        self.update_display_mode();
+
+        imp.settings.connect_changed(
+            Some("display-mode"),
+            clone!(@strong self as this => move |_settings, _name| {
+                this.update_display_mode();
+            }),
+        );
         
         self.imp().settings
             .bind("show-gauge", imp.gauge_box.upcast_ref::<glib::Object>(), "visible")
@@ -182,17 +326,520 @@ impl Window {
             }),
         );
 
+        imp.settings.connect_changed(
+            Some("show-cent-decimals"),
+            clone!(@strong self as this => move |_settings, _name| {
+                let imp = this.imp();
+                imp.show_decimals.set(imp.settings.boolean("show-cent-decimals"));
+            }),
+        );
+
         imp.gauge.replace(Some(gauge));
 
+        imp.history_bin.set_child(Some(&imp.history));
+        imp.settings
+            .bind("show-history", imp.history_bin.upcast_ref::<glib::Object>(), "visible")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
+        imp.strobe.replace(Some(strobe));
+
+        self.update_settings();
+
         self.imp().settings
             .bind("show-title", imp.window_title.upcast_ref::<glib::Object>(), "visible")
             .flags(SettingsBindFlags::DEFAULT)
             .build();
         
+        self.setup_tone_generator();
+        self.setup_file_controls();
+        self.setup_pitch_log();
+        self.setup_osc();
+        self.setup_melody_recording();
+        self.setup_input_device();
         self.setup_channel();
         self.bind_signals();
     }
 
+    fn setup_tone_generator(&self) {
+        let imp = self.imp();
+
+        imp.tone_note_dropdown.set_model(Some(&gtk::StringList::new(&NOTE_NAMES)));
+
+        let note = imp.settings.int("tone-note");
+        imp.tone_note_dropdown.set_selected(note.rem_euclid(12) as u32);
+        imp.tone_octave_adj.set_value((note.div_euclid(12) - 1) as f64);
+        imp.tone_waveform_dropdown.set_selected(imp.settings.enum_("tone-waveform") as u32);
+
+        imp.settings
+            .bind("tone-volume", &*imp.tone_volume_adj, "value")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
+
+        imp.settings
+            .bind("tone-drone", &*imp.tone_drone_switch, "active")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
+
+        imp.tone_note_dropdown.connect_selected_notify(clone!(@weak self as this => move |_| {
+            this.save_tone_note();
+        }));
+
+        imp.tone_octave_adj.connect_value_changed(clone!(@weak self as this => move |_| {
+            this.save_tone_note();
+        }));
+
+        imp.tone_waveform_dropdown.connect_selected_notify(clone!(@weak self as this => move |dropdown| {
+            if let Err(e) = this.imp().settings.set_enum("tone-waveform", dropdown.selected() as i32) {
+                error!("unable to set tone waveform: {}", e);
+            }
+        }));
+
+        //retune or restyle a tone that is already sounding
+        for key in ["tone-note", "tone-waveform", "tone-volume"] {
+            imp.settings.connect_changed(
+                Some(key),
+                clone!(@strong self as this => move |_settings, _name| {
+                    let imp = this.imp();
+                    let playing = imp.tone_generator.borrow().as_ref().map(|g| g.is_playing()).unwrap_or(false);
+                    if playing && imp.settings.boolean("tone-drone") {
+                        this.play_tone();
+                    } else if let Some(generator) = imp.tone_generator.borrow().as_ref() {
+                        generator.update(
+                            Waveform::from_index(imp.settings.enum_("tone-waveform")),
+                            imp.settings.double("tone-volume"),
+                        );
+                    }
+                }),
+            );
+        }
+
+        imp.settings.connect_changed(
+            Some("tone-drone"),
+            clone!(@strong self as this => move |_settings, _name| {
+                if !this.imp().settings.boolean("tone-drone") {
+                    this.stop_tone();
+                }
+            }),
+        );
+
+        imp.tone_play_button.connect_clicked(clone!(@weak self as this => move |_| {
+            this.play_tone();
+        }));
+
+        imp.tone_stop_button.connect_clicked(clone!(@weak self as this => move |_| {
+            this.stop_tone();
+        }));
+    }
+
+    fn setup_file_controls(&self) {
+        let imp = self.imp();
+
+        imp.settings
+            .bind("file-realtime", &*imp.file_realtime_button, "active")
+            .flags(SettingsBindFlags::DEFAULT)
+            .build();
+
+        imp.settings.connect_changed(
+            Some("file-realtime"),
+            clone!(@strong self as this => move |_settings, _name| {
+                let imp = this.imp();
+                if let Some(source) = imp.file_source.borrow().as_ref() {
+                    source.set_realtime(imp.settings.boolean("file-realtime"));
+                }
+            }),
+        );
+
+        imp.file_play_button.connect_toggled(clone!(@weak self as this => move |button| {
+            let imp = this.imp();
+            let (icon, tooltip) = match button.is_active() {
+                true => ("media-playback-pause-symbolic", i18n("Pause")),
+                false => ("media-playback-start-symbolic", i18n("Play")),
+            };
+            button.set_icon_name(icon);
+            button.set_tooltip_text(Some(&tooltip));
+
+            if let Some(source) = imp.file_source.borrow().as_ref() {
+                match button.is_active() {
+                    true => source.play(),
+                    false => source.pause(),
+                }
+            }
+        }));
+
+        //change-value is only emitted for user interaction, not for position updates
+        imp.file_scale.connect_change_value(clone!(@weak self as this => @default-return gtk::Inhibit(false), move |_, _, value| {
+            let imp = this.imp();
+            if let Some(source) = imp.file_source.borrow().as_ref() {
+                source.seek(value);
+                if imp.file_play_button.is_active() {
+                    source.play();
+                } else {
+                    imp.file_play_button.set_active(true);
+                }
+            }
+            gtk::Inhibit(false)
+        }));
+
+        imp.file_close_button.connect_clicked(clone!(@weak self as this => move |_| {
+            this.close_file();
+        }));
+    }
+
+    pub fn choose_audio_file(&self) {
+        let dialog = gtk::FileChooserNative::new(
+            Some(&i18n("Open Audio File")),
+            Some(self),
+            gtk::FileChooserAction::Open,
+            Some(&i18n("_Open")),
+            Some(&i18n("_Cancel")),
+        );
+
+        let filter = gtk::FileFilter::new();
+        filter.set_name(Some(&i18n("Audio Files")));
+        for mime_type in ["audio/x-wav", "audio/wav", "audio/flac", "audio/x-flac", "audio/ogg", "audio/x-vorbis+ogg"] {
+            filter.add_mime_type(mime_type);
+        }
+        dialog.add_filter(&filter);
+
+        dialog.connect_response(
+            clone!(@weak self as this => move |dialog, response| {
+                if response == gtk::ResponseType::Accept {
+                    match dialog.file().and_then(|file| file.path()) {
+                        Some(path) => this.open_file(&path),
+                        None => error!("unable to get path of chosen file"),
+                    }
+                }
+                this.imp().file_chooser.replace(None);
+            }),
+        );
+
+        dialog.show();
+
+        //native dialogs have to be kept alive until they respond
+        self.imp().file_chooser.replace(Some(dialog));
+    }
+
+    //analyse a file instead of the microphone until it is closed
+    pub fn open_file(&self, path: &Path) {
+        let imp = self.imp();
+
+        let sender = match imp.recorder.sender() {
+            Some(sender) => sender,
+            None => return,
+        };
+
+        let source = match FileSource::open(path, AnalysisSettings::load(&imp.settings), imp.settings.boolean("file-realtime"), sender) {
+            Ok(source) => source,
+            Err(e) => {
+                error!("unable to open {}: {}", path.display(), e);
+                toasts::add_error_toast(i18n_k("Unable to open audio file ({error})", &[("error", &e.to_string())]));
+                return;
+            }
+        };
+
+        imp.recorder.suspend();
+
+        let name = source.path().file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        imp.file_label.set_label(&name);
+        imp.file_position_adj.set_upper(source.duration().unwrap_or(0.0));
+        imp.file_position_adj.set_value(0.0);
+        imp.file_scale.set_sensitive(source.duration().is_some());
+        imp.file_revealer.set_reveal_child(true);
+
+        //the previous file's thread ends when its source is dropped
+        imp.file_source.replace(Some(source));
+
+        if imp.file_play_button.is_active() {
+            imp.file_source.borrow().as_ref().unwrap().play();
+        } else {
+            imp.file_play_button.set_active(true);
+        }
+    }
+
+    pub fn close_file(&self) {
+        let imp = self.imp();
+
+        imp.file_source.replace(None);
+        imp.file_play_button.set_active(false);
+        imp.file_revealer.set_reveal_child(false);
+
+        if let Err(e) = imp.recorder.resume() {
+            error!("unable to resume recording: {}", e);
+            toasts::add_error_toast(i18n("Unable to initialize audio backend."));
+        }
+    }
+
+    fn setup_pitch_log(&self) {
+        self.imp().log_button.connect_toggled(clone!(@weak self as this => move |button| {
+            let imp = this.imp();
+            if !button.is_active() {
+                //dropping the log flushes and closes the file
+                imp.pitch_log.replace(None);
+                return;
+            }
+
+            if imp.pitch_log.borrow().is_none() {
+                this.choose_log_file();
+            }
+        }));
+    }
+
+    fn choose_log_file(&self) {
+        let dialog = gtk::FileChooserNative::new(
+            Some(&i18n("Record Pitch Log")),
+            Some(self),
+            gtk::FileChooserAction::Save,
+            Some(&i18n("_Record")),
+            Some(&i18n("_Cancel")),
+        );
+        dialog.set_current_name("pitch-log.jsonl");
+
+        for (name, pattern) in [(i18n("JSON Lines"), "*.jsonl"), (i18n("CSV"), "*.csv")] {
+            let filter = gtk::FileFilter::new();
+            filter.set_name(Some(&name));
+            filter.add_pattern(pattern);
+            dialog.add_filter(&filter);
+        }
+
+        dialog.connect_response(
+            clone!(@weak self as this => move |dialog, response| {
+                let imp = this.imp();
+                let path = match response {
+                    gtk::ResponseType::Accept => dialog.file().and_then(|file| file.path()),
+                    _ => None,
+                };
+
+                if let Some(path) = path {
+                    this.start_log(&path, LogFormat::from_path(&path));
+                }
+
+                if imp.pitch_log.borrow().is_none() {
+                    imp.log_button.set_active(false);
+                }
+                imp.file_chooser.replace(None);
+            }),
+        );
+
+        dialog.show();
+        self.imp().file_chooser.replace(Some(dialog));
+    }
+
+    //restart the stream when the device settings change, whether from the
+    //preferences or over d-bus. both keys are often written at once, so the
+    //restart waits for the pair
+    fn setup_input_device(&self) {
+        let imp = self.imp();
+
+        for key in ["choose-device", "selected-device"] {
+            imp.settings.connect_changed(
+                Some(key),
+                clone!(@strong self as this => move |_settings, _name| {
+                    if this.imp().device_restart_pending.replace(true) {
+                        return;
+                    }
+                    glib::idle_add_local_once(clone!(@strong this => move || {
+                        this.imp().device_restart_pending.set(false);
+                        this.follow_input_device();
+                    }));
+                }),
+            );
+        }
+    }
+
+    fn follow_input_device(&self) {
+        let imp = self.imp();
+        let device_name = imp.settings.string("selected-device").to_string();
+
+        //the recorder writes the device it starts streaming from itself
+        if imp.recorder.device_name().as_deref() == Some(device_name.as_str()) {
+            debug!("window -> already streaming from {}", device_name);
+            return;
+        }
+
+        if let Err(e) = imp.recorder.switch_stream(None) {
+            error!("unable to switch input device: {}", e);
+        }
+    }
+
+    fn setup_osc(&self) {
+        let imp = self.imp();
+
+        for key in ["osc-enabled", "osc-host", "osc-port"] {
+            imp.settings.connect_changed(
+                Some(key),
+                clone!(@strong self as this => move |_settings, _name| {
+                    this.update_osc();
+                }),
+            );
+        }
+
+        self.update_osc();
+    }
+
+    fn update_osc(&self) {
+        let imp = self.imp();
+
+        if !imp.settings.boolean("osc-enabled") {
+            imp.osc.replace(None);
+            return;
+        }
+
+        let host = imp.settings.string("osc-host").to_string();
+        let port = imp.settings.int("osc-port") as u16;
+
+        match OscSender::new(&host, port) {
+            Ok(sender) => {
+                imp.osc.replace(Some(sender));
+            },
+            Err(e) => {
+                imp.osc.replace(None);
+                error!("unable to send osc to {}:{}: {}", host, port, e);
+                toasts::add_error_toast(i18n_k("Unable to send OSC to {host} ({error})", &[("host", &host), ("error", &e.to_string())]));
+            },
+        }
+    }
+
+    fn setup_melody_recording(&self) {
+        self.imp().melody_button.connect_toggled(clone!(@weak self as this => move |button| {
+            let imp = this.imp();
+            if button.is_active() {
+                imp.melody.replace(Some(MelodyRecorder::new(imp.base_pitch.get())));
+                return;
+            }
+
+            let notes = match imp.melody.take() {
+                Some(melody) => melody.finish(),
+                None => return,
+            };
+
+            if notes.is_empty() {
+                toasts::add_error_toast(i18n("No notes were recorded."));
+            } else {
+                this.choose_midi_file(notes);
+            }
+        }));
+    }
+
+    fn choose_midi_file(&self, notes: Vec<MelodyNote>) {
+        let dialog = gtk::FileChooserNative::new(
+            Some(&i18n("Export Melody")),
+            Some(self),
+            gtk::FileChooserAction::Save,
+            Some(&i18n("_Export")),
+            Some(&i18n("_Cancel")),
+        );
+        dialog.set_current_name("melody.mid");
+
+        let filter = gtk::FileFilter::new();
+        filter.set_name(Some(&i18n("MIDI Files")));
+        filter.add_mime_type("audio/midi");
+        filter.add_pattern("*.mid");
+        dialog.add_filter(&filter);
+
+        dialog.connect_response(
+            clone!(@weak self as this => move |dialog, response| {
+                if response == gtk::ResponseType::Accept {
+                    if let Some(path) = dialog.file().and_then(|file| file.path()) {
+                        match melody::write_midi(&notes, &path) {
+                            Ok(_) => toasts::add_success_toast(&i18n("Exported"), &i18n_k("{count} notes", &[("count", &notes.len().to_string())])),
+                            Err(e) => {
+                                error!("unable to write {}: {}", path.display(), e);
+                                toasts::add_error_toast(i18n_k("Unable to export melody ({error})", &[("error", &e.to_string())]));
+                            },
+                        }
+                    }
+                }
+                this.imp().file_chooser.replace(None);
+            }),
+        );
+
+        dialog.show();
+        self.imp().file_chooser.replace(Some(dialog));
+    }
+
+    //log every analysed frame to path, "-" for stdout
+    pub fn start_log(&self, path: &Path, format: LogFormat) {
+        let imp = self.imp();
+        match PitchLog::create(path, format) {
+            Ok(log) => {
+                imp.pitch_log.replace(Some(log));
+                imp.log_button.set_active(true);
+            },
+            Err(e) => {
+                error!("unable to create {}: {}", path.display(), e);
+                toasts::add_error_toast(i18n_k("Unable to create pitch log ({error})", &[("error", &e.to_string())]));
+            },
+        }
+    }
+
+    fn log_frame(&self, frequency: f32, confidence: f32, note: Option<&Note>) {
+        let imp = self.imp();
+        let result = match imp.pitch_log.borrow_mut().as_mut() {
+            Some(log) => log.write_frame(frequency, confidence, note),
+            None => return,
+        };
+
+        if let Err(e) = result {
+            error!("unable to write pitch log: {}", e);
+            toasts::add_error_toast(i18n_k("Pitch log stopped ({error})", &[("error", &e.to_string())]));
+            imp.log_button.set_active(false);
+        }
+    }
+
+    fn save_tone_note(&self) {
+        let imp = self.imp();
+        let note = 12 * (imp.tone_octave_adj.value() as i32 + 1) + imp.tone_note_dropdown.selected() as i32;
+        if let Err(e) = imp.settings.set_int("tone-note", note) {
+            error!("unable to set tone note: {}", e);
+        }
+    }
+
+    fn play_tone(&self) {
+        let imp = self.imp();
+
+        //the output stream is opened from the recorder's portaudio instance
+        if imp.tone_generator.borrow().is_none() {
+            match imp.recorder.portaudio() {
+                Some(pa) => {
+                    imp.tone_generator.replace(Some(ToneGenerator::new(pa)));
+                }
+                None => {
+                    toasts::add_error_toast(i18n("Unable to play tone, audio backend not initialized."));
+                    return;
+                }
+            }
+        }
+
+        let frequency = tone_generator::note_frequency(imp.settings.int("tone-note"), imp.settings.double("reference-pitch"));
+        let result = imp.tone_generator.borrow_mut().as_mut().unwrap().play(
+            frequency,
+            Waveform::from_index(imp.settings.enum_("tone-waveform")),
+            imp.settings.double("tone-volume"),
+            imp.settings.boolean("tone-drone"),
+        );
+
+        if let Err(e) = result {
+            error!("unable to play tone: {}", e);
+            toasts::add_error_toast(i18n_k("Unable to play tone ({error})", &[("error", &e.to_string())]));
+        }
+    }
+
+    fn stop_tone(&self) {
+        if let Some(generator) = self.imp().tone_generator.borrow().as_ref() {
+            generator.stop();
+        }
+    }
+
+    fn update_display_mode(&self) {
+        let imp = self.imp();
+        match imp.settings.enum_("display-mode") {
+            1 => imp.display_stack.set_visible_child_name("strobe"),
+            2 => imp.display_stack.set_visible_child_name("spectrum"),
+            3 => imp.display_stack.set_visible_child_name("oscilloscope"),
+            _ => imp.display_stack.set_visible_child_name("gauge"),
+        }
+    }
+
     fn setup_channel(&self) {
         let imp = self.imp();
         let receiver = imp.receiver.borrow_mut().take().unwrap();
@@ -205,11 +852,44 @@ impl Window {
 
     fn process_action(&self, action: AudioAction) -> glib::Continue {
         match action {
-            AudioAction::RawAudio(buffer) => {
-                debug!("BUFFER {:?}", buffer);
+            AudioAction::RawAudio(buffer, sample_rate) => {
+                //only analyse while a view of the waveform is on screen
+                let imp = self.imp();
+                if imp.show_gauge.get() {
+                    match imp.display_stack.visible_child_name().as_deref() {
+                        Some("spectrum") => imp.spectrum.set_buffer(&buffer, sample_rate),
+                        Some("oscilloscope") => imp.oscilloscope.set_buffer(&buffer, sample_rate),
+                        _ => (),
+                    }
+                }
+            },
+            AudioAction::Pitch(freq, confidence, duration) => {
+                let note = self.update_frequency(freq, confidence);
+                self.log_frame(freq, confidence, note.as_ref());
+                if let Some(melody) = self.imp().melody.borrow_mut().as_mut() {
+                    melody.add_frame(duration, note.as_ref().map(|_| freq as f64));
+                }
+                if let (Some(osc), Some(note)) = (self.imp().osc.borrow().as_ref(), note.as_ref()) {
+                    //nobody listening is not an error for udp, so only note it
+                    if let Err(e) = osc.send_pitch(freq, note) {
+                        debug!("unable to send osc: {}", e);
+                    }
+                }
+                if let Some(app) = self.application().and_then(|a| a.downcast::<App>().ok()) {
+                    app.publish_pitch(note.as_ref(), freq);
+                }
+            },
+            AudioAction::Level(rms, peak) => {
+                self.update_level(rms, peak);
+            },
+            AudioAction::FilePosition(position) => {
+                self.imp().file_position_adj.set_value(position);
+            },
+            AudioAction::FileFinished => {
+                self.imp().file_play_button.set_active(false);
             },
-            AudioAction::Pitch(freq) => {
-                self.update_frequency(freq);
+            AudioAction::Error(message) => {
+                toasts::add_error_toast(message);
             },
             // _ => debug!("Received action {:?}", action),
         }
@@ -228,7 +908,7 @@ impl Window {
                 match freq_val {
                     Some(freq_val) => {
                         let freq = freq_val.get::<f32>().ok().unwrap();
-                        this.update_frequency(freq);
+                        this.update_frequency(freq, 1.0);
                     },
                     None => (),
                 }
@@ -249,12 +929,100 @@ impl Window {
 
     pub fn update_settings(&self) {
         let imp = self.imp();
-        imp.hang_duration.set((1000.0 * imp.settings.double("label-hang")) as u64)
+        imp.hang_duration.set((1000.0 * imp.settings.double("label-hang")) as u64);
+        imp.base_pitch.set(imp.settings.double("reference-pitch"));
+        imp.spectrum.set_reference(imp.base_pitch.get());
+        imp.transposition.set(imp.settings.int("transposition"));
+        imp.minimum_confidence.set(imp.settings.double("minimum-confidence") as f32);
+        imp.show_decimals.set(imp.settings.boolean("show-cent-decimals"));
+        self.load_tuning();
+        self.load_instrument();
+
+        //strings are measured in semitones, whatever the tuning system
+        let range = match imp.instrument.borrow().is_some() {
+            true => 100.0,
+            false => imp.tuning.borrow().step_size(),
+        };
+
+        if let Some(gauge) = imp.gauge.borrow().as_ref() {
+            gauge.set_gauge_range(range);
+        }
+    }
+
+    fn load_instrument(&self) {
+        let imp = self.imp();
+        let preset = instruments::selected_preset(&imp.settings);
+
+        while let Some(child) = imp.strings_box.first_child() {
+            imp.strings_box.remove(&child);
+        }
+
+        let labels: Vec<gtk::Label> = match preset.as_ref() {
+            Some(preset) => preset.string_names().iter().map(|name| {
+                let label = gtk::Label::new(Some(name.as_str()));
+                label.add_css_class("string-label");
+                label.add_css_class("dim-label");
+                imp.strings_box.append(&label);
+                label
+            }).collect(),
+            None => Vec::new(),
+        };
+
+        imp.strings_box.set_visible(preset.is_some());
+        imp.string_labels.replace(labels);
+        imp.instrument.replace(preset);
+    }
+
+    //highlight the string being tuned
+    fn select_string(&self, index: Option<usize>) {
+        for (i, label) in self.imp().string_labels.borrow().iter().enumerate() {
+            if Some(i) == index {
+                label.remove_css_class("dim-label");
+                label.add_css_class("accent");
+            } else {
+                label.remove_css_class("accent");
+                label.add_css_class("dim-label");
+            }
+        }
+    }
+
+    fn load_tuning(&self) {
+        let imp = self.imp();
+        let tuning = match TuningSystem::from_settings(&imp.settings) {
+            Ok(tuning) => tuning,
+            Err(e) => {
+                error!("unable to load scala tuning: {}", e);
+                toasts::add_error_toast(i18n_k("Unable to load Scala file ({error})", &[("error", &e.to_string())]));
+                TuningSystem::temperament(&imp.settings)
+            }
+        };
+
+        imp.tuning.replace(tuning);
+    }
+
+    fn update_level(&self, rms: f32, peak: f32) {
+        let imp = self.imp();
+        imp.level_bar.set_value(util::level_fraction(rms));
+
+        //input quieter than the silence threshold is not analysed, show that it is not heard
+        if rms < imp.settings.double("silence-threshold") as f32 {
+            imp.level_bar.add_css_class("dim-label");
+        } else {
+            imp.level_bar.remove_css_class("dim-label");
+        }
+
+        imp.recorder.emit_by_name::<()>("level", &[&rms, &peak]);
     }
 
-    pub fn update_frequency(&self, frequency: f32) {
+    //the note heard, None when the frame counts as silence
+    pub fn update_frequency(&self, frequency: f32, confidence: f32) -> Option<Note> {
         let imp = self.imp();
-        if frequency <= 0.0 {
+        //unreliable frames are most likely noise, so handle them like silence
+        if frequency <= 0.0 || confidence < imp.minimum_confidence.get() {
+            imp.history.add_sample(None);
+            imp.spectrum.set_fundamental(None);
+            imp.oscilloscope.set_frequency(None);
+
             if imp.hang_time.borrow().is_none() {
                 imp.hang_time.replace(Some(Instant::now()));
             } else {
@@ -263,60 +1031,65 @@ impl Window {
                     imp.frequency_label.set_label("-- Hz");
                     imp.cents_label.set_label("");
                     imp.hang_time.replace(None);
+                    self.select_string(None);
+                    if let Some(strobe) = imp.strobe.borrow().as_ref() {
+                        strobe.set_deviation(None);
+                    }
                 }
             }
 
+            None
         } else {
             if !imp.hang_time.borrow().is_none() {
                 imp.hang_time.replace(None);
             }
 
             imp.frequency_label.set_label(&format!("{:.2} Hz", frequency));
+            imp.spectrum.set_fundamental(Some(frequency as f64));
+            imp.oscilloscope.set_frequency(Some(frequency as f64));
+
+            let string = imp.instrument.borrow().as_ref().and_then(|preset| {
+                preset.nearest_string(frequency as f64, imp.base_pitch.get(), imp.transposition.get())
+            });
 
-            let letter_octave = Hz(frequency).letter_octave();
-
-            let letter = match letter_octave.0 as u64 {
-                0 => "C",
-                1 => "C♯",
-                2 => "D♭",
-                3 => "D",
-                4 => "D♯",
-                5 => "E♭",
-                6 => "E",
-                7 => "F",
-                8 => "F♯",
-                9 => "G♭",
-                10 => "G",
-                11 => "G♯",
-                12 => "A♭",
-                13 => "A",
-                14 => "A♯",
-                15 => "B♭",
-                16 => "B",
-                17_u64..=u64::MAX => "?",
+            let note = match string {
+                Some((index, note)) => {
+                    self.select_string(Some(index));
+                    note
+                }
+                None => imp.tuning.borrow().nearest_note(frequency as f64, imp.base_pitch.get(), imp.transposition.get()),
             };
 
-            imp.note_label.set_label(&format!("<span size=\"400%\">{}</span><span baseline_shift=\"subscript\" size=\"150%\">{}</span>", letter, letter_octave.1));
+            imp.note_label.set_label(&format!("<span size=\"400%\">{}</span><span baseline_shift=\"subscript\" size=\"150%\">{}</span>", note.name, note.octave));
 
-            let closest_freq = LetterOctave(letter_octave.0, letter_octave.1).to_hz();
+            let cents = note.cents;
 
-            let cents = (1200.0 * (frequency / closest_freq.0).log2()) as i32;
-                        
-            if cents > 0 {
-                imp.cents_label.set_label(&format!("+{} cents", cents));
-            } else if cents < 0 {
-                imp.cents_label.set_label(&format!("{} cents", cents));
-            } else {
+            //round for display only, the gauge gets the exact deviation
+            let shown = match imp.show_decimals.get() {
+                true => (cents * 10.0).round() / 10.0,
+                false => cents.round(),
+            };
+
+            if shown == 0.0 {
                 imp.cents_label.set_label("");
+            } else if imp.show_decimals.get() {
+                imp.cents_label.set_label(&format!("{:+.1} cents", shown));
+            } else {
+                imp.cents_label.set_label(&format!("{:+} cents", shown));
             }
 
             imp.cents.set(cents);
 
+            //written pitch in equal tempered semitones, for the note axis of the history
+            let position = 69.0 + 12.0 * (frequency as f64 / imp.base_pitch.get()).log2() + imp.transposition.get() as f64;
+            imp.history.add_sample(Some(PitchSample { cents, position }));
+
             if self.imp().show_gauge.get() {
                 imp.gauge.borrow().as_ref().unwrap().set_gauge_position(cents);
+                imp.strobe.borrow().as_ref().unwrap().set_deviation(Some(cents));
             }
 
-            
+            Some(note)
         }
     }
 